@@ -0,0 +1,328 @@
+use crate::codec::{http::has_token, http_client::HttpClient, Decoder, Encoder};
+use crate::context::Body;
+use bytes::BytesMut;
+use http::header::{HeaderValue, CONNECTION, HOST};
+use http::{Method, Request, Response, StatusCode, Uri, Version};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// HTTP/1.1 client keeping connections alive between requests.
+///
+/// Idle connections are pooled per host and port, a pooled connection the
+/// server closed in the meantime is replaced by a fresh one transparently.
+/// Requests that are not idempotent, such as `POST`, are only sent again if
+/// the server closed the connection before they could be written.
+/// The client is cheap to clone, clones share the pool. Only `http://` urls
+/// are supported.
+#[derive(Clone)]
+pub struct Client {
+    pool: Arc<Mutex<HashMap<String, Vec<Idle>>>>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    idle_timeout: Duration,
+    max_idle: usize,
+    max_response_size: usize,
+}
+
+struct Idle {
+    stream: TcpStream,
+    since: Instant,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            pool: Arc::new(Mutex::new(HashMap::new())),
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(60),
+            max_idle: 8,
+            max_response_size: 64 << 20,
+        }
+    }
+
+    /// How long to wait for a connection to be established. Defaults to 10
+    /// seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long a whole request may take, from connecting to reading the
+    /// last byte of the response. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long an idle connection is kept for reuse. Defaults to 60
+    /// seconds.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// How many idle connections are kept per host. Defaults to 8, 0
+    /// disables keep-alive.
+    pub fn max_idle_per_host(mut self, max: usize) -> Self {
+        self.max_idle = max;
+        self
+    }
+
+    /// The largest response body accepted, larger ones fail with
+    /// `ClientError::InvalidResponse`. Defaults to 64 MiB.
+    pub fn max_response_size(mut self, size: usize) -> Self {
+        self.max_response_size = size;
+        self
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response<Body>, ClientError> {
+        self.send(request(Method::GET, url, Body::empty())?).await
+    }
+
+    pub async fn post(
+        &self,
+        url: &str,
+        body: impl Into<Body>,
+    ) -> Result<Response<Body>, ClientError> {
+        self.send(request(Method::POST, url, body.into())?).await
+    }
+
+    /// Sends a request to the absolute `http://` url in its uri. `Host` is
+    /// filled in from the url unless set.
+    pub async fn send(&self, mut req: Request<Body>) -> Result<Response<Body>, ClientError> {
+        let uri = req.uri().clone();
+
+        let (host, port) = match (uri.scheme_str(), uri.host()) {
+            (Some("http"), Some(host)) => (host, uri.port_u16().unwrap_or(80)),
+            _ => return Err(ClientError::InvalidUrl),
+        };
+
+        if !req.headers().contains_key(HOST) {
+            let authority = uri.authority().unwrap().as_str();
+            let host = HeaderValue::from_str(authority).map_err(|_| ClientError::InvalidUrl)?;
+            req.headers_mut().insert(HOST, host);
+        }
+
+        let keep_alive =
+            req.version() == Version::HTTP_11 && !has_token(req.headers(), CONNECTION, "close");
+
+        let method = req.method().clone();
+        let mut buf = BytesMut::new();
+        HttpClient::new().encode(req, &mut buf).unwrap();
+
+        let key = format!("{}:{}", host, port);
+        let exchange = self.exchange(&key, host, port, &buf, &method, keep_alive);
+
+        match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(res) => res,
+            Err(_) => Err(ClientError::Timeout),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        key: &str,
+        host: &str,
+        port: u16,
+        req: &[u8],
+        method: &Method,
+        keep_alive: bool,
+    ) -> Result<Response<Body>, ClientError> {
+        let head = method == Method::HEAD;
+
+        loop {
+            let (mut stream, reused) = match self.checkout(key) {
+                Some(stream) => (stream, true),
+                None => (self.connect(host, port).await?, false),
+            };
+
+            match round_trip(&mut stream, req, head, self.max_response_size).await {
+                Ok((resp, reusable)) => {
+                    if keep_alive && reusable {
+                        self.checkin(key, stream);
+                    }
+
+                    return Ok(resp);
+                }
+                // the server closed the idle connection before the request
+                // could be written, it was not processed
+                Err(Exchange::Unsent(_)) if reused => continue,
+                // or closed it without answering, which may also mean that
+                // the request failed, so it is only sent again if that is
+                // harmless
+                Err(Exchange::Stale) if reused && method.is_idempotent() => continue,
+                Err(Exchange::Unsent(err)) => return Err(ClientError::IO(err)),
+                Err(Exchange::Stale) => {
+                    let err = std::io::ErrorKind::UnexpectedEof.into();
+                    return Err(ClientError::IO(err));
+                }
+                Err(Exchange::Failed(err)) => return Err(err),
+            }
+        }
+    }
+
+    async fn connect(&self, host: &str, port: u16) -> Result<TcpStream, ClientError> {
+        let connect = TcpStream::connect((host, port));
+
+        let stream = match tokio::time::timeout(self.connect_timeout, connect).await {
+            Ok(stream) => stream?,
+            Err(_) => return Err(ClientError::Timeout),
+        };
+
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    fn checkout(&self, key: &str) -> Option<TcpStream> {
+        let mut pool = self.pool.lock().unwrap();
+        let idle = pool.get_mut(key)?;
+
+        while let Some(conn) = idle.pop() {
+            if conn.since.elapsed() < self.idle_timeout {
+                return Some(conn.stream);
+            }
+        }
+
+        None
+    }
+
+    fn checkin(&self, key: &str, stream: TcpStream) {
+        let mut pool = self.pool.lock().unwrap();
+        let idle = pool.entry(key.to_string()).or_default();
+        idle.retain(|conn| conn.since.elapsed() < self.idle_timeout);
+
+        if idle.len() < self.max_idle {
+            idle.push(Idle {
+                stream,
+                since: Instant::now(),
+            });
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn request(method: Method, url: &str, body: Body) -> Result<Request<Body>, ClientError> {
+    let uri: Uri = url.parse().map_err(|_| ClientError::InvalidUrl)?;
+
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .map_err(|_| ClientError::InvalidUrl)
+}
+
+enum Exchange {
+    /// The connection was closed before the request was written.
+    Unsent(std::io::Error),
+    /// The connection was closed before any of the response arrived.
+    Stale,
+    Failed(ClientError),
+}
+
+impl From<std::io::Error> for Exchange {
+    fn from(err: std::io::Error) -> Self {
+        Self::Failed(err.into())
+    }
+}
+
+/// Writes a request and reads its response, returning whether the
+/// connection can carry another request.
+async fn round_trip(
+    stream: &mut TcpStream,
+    req: &[u8],
+    head: bool,
+    max_body: usize,
+) -> Result<(Response<Body>, bool), Exchange> {
+    if let Err(err) = stream.write_all(req).await {
+        return match err.kind() {
+            std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::ConnectionReset => {
+                Err(Exchange::Unsent(err))
+            }
+            _ => Err(err.into()),
+        };
+    }
+
+    let mut codec = HttpClient::new();
+    codec.set_head_request(head);
+    codec.set_max_body_size(max_body);
+
+    let mut buf = BytesMut::with_capacity(8192);
+    let mut received = false;
+
+    loop {
+        let n = match stream.read_buf(&mut buf).await {
+            Ok(n) => n,
+            Err(err) if !received && err.kind() == std::io::ErrorKind::ConnectionReset => {
+                return Err(Exchange::Stale)
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if n == 0 {
+            if !received {
+                return Err(Exchange::Stale);
+            }
+
+            return match codec.decode_eof(&mut buf) {
+                Ok(Some(resp)) => Ok((resp, false)),
+                _ => Err(Exchange::Failed(ClientError::InvalidResponse)),
+            };
+        }
+
+        received = true;
+
+        match codec.decode(&mut buf) {
+            Ok(Some(resp)) => {
+                let reusable = buf.is_empty()
+                    && resp.version() == Version::HTTP_11
+                    && resp.status() != StatusCode::SWITCHING_PROTOCOLS
+                    && !has_token(resp.headers(), CONNECTION, "close");
+
+                return Ok((resp, reusable));
+            }
+            Ok(None) => {}
+            Err(_) => return Err(Exchange::Failed(ClientError::InvalidResponse)),
+        }
+    }
+}
+
+/// Why a request failed.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The url is not an `http://` url with a host.
+    InvalidUrl,
+    /// The connection or the whole request took too long.
+    Timeout,
+    /// The server sent something that is not a valid HTTP/1.x response.
+    InvalidResponse,
+    IO(std::io::Error),
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(err: std::io::Error) -> Self {
+        Self::IO(err)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl => f.write_str("invalid http url"),
+            Self::Timeout => f.write_str("request timed out"),
+            Self::InvalidResponse => f.write_str("invalid http response"),
+            Self::IO(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
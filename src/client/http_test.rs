@@ -0,0 +1,161 @@
+use crate::client::{Client, ClientError};
+use bytes::BytesMut;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Reads a request head, good enough for the bodiless requests below.
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut buf = BytesMut::new();
+
+    while !buf.ends_with(b"\r\n\r\n") {
+        if stream.read_buf(&mut buf).await.ok()? == 0 {
+            return None;
+        }
+    }
+
+    Some(String::from_utf8(buf.to_vec()).unwrap())
+}
+
+fn body(resp: &http::Response<crate::context::Body>) -> BytesMut {
+    let mut out = BytesMut::new();
+    resp.body().bytes(&mut out);
+    out
+}
+
+#[tokio::test]
+async fn client_reuses_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hello", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut served = 0;
+
+        while let Some(req) = read_request(&mut stream).await {
+            assert!(req.starts_with("GET /hello HTTP/1.1\r\n"));
+            assert!(req.contains("host: 127.0.0.1:"));
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            served += 1;
+        }
+
+        served
+    });
+
+    let client = Client::new();
+    for _ in 0..3 {
+        let resp = client.get(&url).await.unwrap();
+        assert_eq!(&body(&resp)[..], b"ok");
+    }
+
+    drop(client);
+    assert_eq!(server.await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn client_retries_stale_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        // answers a single request per connection, without saying so
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        }
+    });
+
+    let client = Client::new();
+    client.get(&url).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let resp = client.get(&url).await.unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn client_does_not_resend_unanswered_posts() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        read_request(&mut stream).await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+
+        // the POST on the reused connection is read, then never answered
+        let req = read_request(&mut stream).await.unwrap();
+        assert!(req.starts_with("POST / HTTP/1.1\r\n"));
+        drop(stream);
+
+        // and must not arrive a second time
+        let accept = tokio::time::timeout(Duration::from_millis(200), listener.accept());
+        accept.await.is_err()
+    });
+
+    let client = Client::new();
+    client.get(&url).await.unwrap();
+    assert!(matches!(
+        client.post(&url, "").await,
+        Err(ClientError::IO(_))
+    ));
+    assert!(server.await.unwrap(), "request sent again");
+}
+
+#[tokio::test]
+async fn client_limits_response_size() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .await
+                .unwrap();
+        }
+    });
+
+    let client = Client::new().max_response_size(5).max_idle_per_host(0);
+    let resp = client.get(&url).await.unwrap();
+    assert_eq!(&body(&resp)[..], b"hello");
+
+    let client = Client::new().max_response_size(4);
+    assert!(matches!(
+        client.get(&url).await,
+        Err(ClientError::InvalidResponse)
+    ));
+}
+
+#[tokio::test]
+async fn client_errors() {
+    let client = Client::new().timeout(Duration::from_millis(100));
+
+    assert!(matches!(
+        client.get("https://localhost/").await,
+        Err(ClientError::InvalidUrl)
+    ));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        read_request(&mut stream).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    });
+
+    assert!(matches!(client.get(&url).await, Err(ClientError::Timeout)));
+}
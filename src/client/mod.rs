@@ -0,0 +1,10 @@
+mod http;
+mod ws;
+
+pub use self::http::{Client, ClientError};
+pub use ws::{WsClient, WsClientError};
+
+#[cfg(test)]
+mod http_test;
+#[cfg(test)]
+mod ws_test;
@@ -0,0 +1,267 @@
+use crate::codec::{
+    http::has_token,
+    websocket::{Ws, MAX_FRAME, MAX_MESSAGE},
+};
+use crate::context::{Context, Receiver, Sender};
+use crate::ws::WsUpgrader;
+use bytes::BytesMut;
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS,
+    SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+};
+use http::{StatusCode, Uri};
+use rand::RngCore;
+use std::fmt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The largest handshake response accepted from a server.
+const MAX_RESPONSE: usize = 16 << 10;
+
+/// Opens websocket connections to other servers.
+///
+/// The connection is handed out as the same `Sender` and `Receiver` pair
+/// routes get, with outgoing frames masked as clients must. Unlike on the
+/// server, the closing handshake is left to the caller: send a close frame
+/// and read until the server answers with its own.
+#[derive(Debug, Clone)]
+pub struct WsClient {
+    headers: HeaderMap,
+    protocols: Vec<String>,
+    max_frame_size: usize,
+    max_message_size: usize,
+}
+
+impl WsClient {
+    pub fn new() -> Self {
+        Self {
+            headers: HeaderMap::new(),
+            protocols: Vec::new(),
+            max_frame_size: MAX_FRAME,
+            max_message_size: MAX_MESSAGE,
+        }
+    }
+
+    /// Connects to a `ws://` url with the default settings.
+    pub async fn connect(url: &str) -> Result<(Sender<Ws>, Receiver<Ws>), WsClientError> {
+        Self::new().open(url).await
+    }
+
+    /// Adds a header to the upgrade request, e.g. `Authorization` or
+    /// `Origin`.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// The subprotocols to offer, in order of preference. The one the server
+    /// picked is available from `Receiver::protocol`.
+    pub fn protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The largest frame payload accepted from the server, see
+    /// `WsConfig::max_frame_size`.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// The largest message accepted from the server, see
+    /// `WsConfig::max_message_size`.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Connects to a `ws://` url and performs the opening handshake.
+    pub async fn open(&self, url: &str) -> Result<(Sender<Ws>, Receiver<Ws>), WsClientError> {
+        let uri: Uri = url.parse().map_err(|_| WsClientError::InvalidUrl)?;
+
+        let host = match (uri.scheme_str(), uri.host()) {
+            (Some("ws"), Some(host)) => host,
+            _ => return Err(WsClientError::InvalidUrl),
+        };
+
+        let port = uri.port_u16().unwrap_or(80);
+        let mut stream = TcpStream::connect((host, port)).await?;
+        stream.set_nodelay(true)?;
+
+        let mut key = [0; 16];
+        rand::thread_rng().fill_bytes(&mut key);
+        let key = base64::encode(key);
+
+        stream.write_all(&self.request(&uri, &key)).await?;
+
+        let mut buf = BytesMut::with_capacity(1024);
+        let (status, headers) = read_response(&mut stream, &mut buf).await?;
+        let protocol = self.verify(status, &headers, &key)?;
+
+        let (tx, mut rx) = Context::<Ws>::split_client(stream);
+        rx.set_limits(self.max_frame_size, self.max_message_size);
+        rx.extend_buf(&buf);
+
+        if let Some(protocol) = protocol {
+            rx.set_protocol(protocol);
+        }
+
+        Ok((tx, rx))
+    }
+
+    fn request(&self, uri: &Uri, key: &str) -> Vec<u8> {
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+        let authority = uri.authority().map_or("", |a| a.as_str());
+
+        let mut req = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: {}\r\n",
+            path,
+            authority,
+            key,
+            WsUpgrader::VERSION
+        )
+        .into_bytes();
+
+        if !self.protocols.is_empty() {
+            req.extend_from_slice(b"Sec-WebSocket-Protocol: ");
+            req.extend_from_slice(self.protocols.join(", ").as_bytes());
+            req.extend_from_slice(b"\r\n");
+        }
+
+        for (name, value) in &self.headers {
+            req.extend_from_slice(name.as_str().as_bytes());
+            req.extend_from_slice(b": ");
+            req.extend_from_slice(value.as_bytes());
+            req.extend_from_slice(b"\r\n");
+        }
+
+        req.extend_from_slice(b"\r\n");
+        req
+    }
+
+    /// Checks the handshake response, returning the subprotocol the server
+    /// picked.
+    fn verify(
+        &self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        key: &str,
+    ) -> Result<Option<String>, WsClientError> {
+        if status != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(WsClientError::Status(status));
+        }
+
+        if !has_token(headers, UPGRADE, "websocket") || !has_token(headers, CONNECTION, "upgrade") {
+            return Err(WsClientError::Handshake("missing upgrade headers"));
+        }
+
+        let expected = WsUpgrader::accept_key(&HeaderValue::from_str(key).unwrap());
+
+        match headers.get(SEC_WEBSOCKET_ACCEPT) {
+            Some(accept) if accept.as_bytes() == expected.as_bytes() => {}
+            _ => return Err(WsClientError::Handshake("invalid Sec-WebSocket-Accept")),
+        }
+
+        if headers.contains_key(SEC_WEBSOCKET_EXTENSIONS) {
+            return Err(WsClientError::Handshake(
+                "unexpected Sec-WebSocket-Extensions",
+            ));
+        }
+
+        let protocol = match headers.get(SEC_WEBSOCKET_PROTOCOL) {
+            Some(protocol) => protocol.to_str().ok().map(str::to_string),
+            None => return Ok(None),
+        };
+
+        match protocol {
+            Some(protocol) if self.protocols.contains(&protocol) => Ok(Some(protocol)),
+            _ => Err(WsClientError::Handshake(
+                "unexpected Sec-WebSocket-Protocol",
+            )),
+        }
+    }
+}
+
+impl Default for WsClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the head of the handshake response, leaving whatever follows it,
+/// i.e. frames the server sent right away, in `buf`.
+async fn read_response(
+    stream: &mut TcpStream,
+    buf: &mut BytesMut,
+) -> Result<(StatusCode, HeaderMap), WsClientError> {
+    loop {
+        if stream.read_buf(buf).await? == 0 {
+            return Err(WsClientError::Handshake(
+                "connection closed during handshake",
+            ));
+        }
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut resp = httparse::Response::new(&mut headers);
+
+        let len = match resp.parse(buf) {
+            Ok(httparse::Status::Complete(len)) => len,
+            Ok(httparse::Status::Partial) if buf.len() < MAX_RESPONSE => continue,
+            _ => return Err(WsClientError::Handshake("invalid handshake response")),
+        };
+
+        let status = resp
+            .code
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .ok_or(WsClientError::Handshake("invalid handshake response"))?;
+
+        let mut map = HeaderMap::new();
+        for header in resp.headers.iter() {
+            let name = HeaderName::from_bytes(header.name.as_bytes());
+            let value = HeaderValue::from_bytes(header.value);
+
+            if let (Ok(name), Ok(value)) = (name, value) {
+                map.append(name, value);
+            }
+        }
+
+        let _ = buf.split_to(len);
+        return Ok((status, map));
+    }
+}
+
+/// Why a websocket connection could not be opened.
+#[derive(Debug)]
+pub enum WsClientError {
+    /// The url is not a `ws://` url with a host.
+    InvalidUrl,
+    /// The server answered the upgrade request with another status.
+    Status(StatusCode),
+    /// The server's response is not a valid opening handshake.
+    Handshake(&'static str),
+    IO(std::io::Error),
+}
+
+impl From<std::io::Error> for WsClientError {
+    fn from(err: std::io::Error) -> Self {
+        Self::IO(err)
+    }
+}
+
+impl fmt::Display for WsClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl => f.write_str("invalid websocket url"),
+            Self::Status(status) => write!(f, "upgrade rejected with {}", status),
+            Self::Handshake(reason) => f.write_str(reason),
+            Self::IO(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WsClientError {}
@@ -0,0 +1,129 @@
+use crate::client::{WsClient, WsClientError};
+use crate::codec::{
+    http::Http,
+    websocket::{Message, Opcode, Ws, WsFrame},
+    Decoder, Encoder,
+};
+use crate::context::{Body, Context};
+use crate::ws::{WsConfig, WsUpgrader};
+use bytes::BytesMut;
+use http::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Reads the upgrade request sent to `listener`.
+async fn accept(listener: &TcpListener) -> (TcpStream, http::Request<Body>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut buf = BytesMut::new();
+    let mut codec = Http::<Body>::new();
+
+    loop {
+        stream.read_buf(&mut buf).await.unwrap();
+        if let Some(req) = codec.decode(&mut buf).unwrap() {
+            return (stream, req);
+        }
+    }
+}
+
+#[tokio::test]
+async fn ws_client_talks_to_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/echo?a=1", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        let (mut stream, req) = accept(&listener).await;
+        assert_eq!(req.uri(), "/echo?a=1");
+        assert_eq!(req.headers()["authorization"], "Bearer x");
+
+        let config = WsConfig::new().protocols(["b"]);
+        WsUpgrader::validate(&req, &config).unwrap();
+        let (resp, _) = WsUpgrader::accept(&req, &config).unwrap();
+        WsUpgrader::upgrade(&mut stream, resp).await.ok().unwrap();
+
+        // echo until the client closes, the server codec only accepts
+        // masked frames, including the pong the client answers with
+        let (mut tx, mut rx) = Context::<Ws>::split(stream);
+        tx.send(Message::ping("p")).await.unwrap();
+
+        loop {
+            let msg = rx.recv().await.unwrap();
+            match msg.opcode() {
+                Opcode::CLOSE => break tx.send(msg).await.unwrap(),
+                Opcode::TEXT => tx.send(msg).await.unwrap(),
+                _ => {}
+            }
+        }
+    });
+
+    let (mut tx, mut rx) = WsClient::new()
+        .protocols(["a", "b"])
+        .header(
+            http::header::AUTHORIZATION,
+            http::HeaderValue::from_static("Bearer x"),
+        )
+        .open(&url)
+        .await
+        .unwrap();
+    assert_eq!(rx.protocol(), Some("b"));
+
+    tx.send(Message::text("hi")).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().as_str(), Some("hi"));
+
+    tx.close(crate::codec::websocket::CloseCode::Normal, "")
+        .await
+        .unwrap();
+    assert_eq!(rx.recv().await.unwrap().opcode(), &Opcode::CLOSE);
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn ws_client_verifies_handshake() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        for resp in [
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+        ] {
+            let (mut stream, _) = accept(&listener).await;
+            stream.write_all(resp.as_bytes()).await.unwrap();
+        }
+    });
+
+    match WsClient::connect(&url).await {
+        Err(WsClientError::Status(status)) => assert_eq!(status, StatusCode::FORBIDDEN),
+        res => panic!("unexpected {:?}", res.map(|_| ())),
+    }
+
+    match WsClient::connect(&url).await {
+        Err(WsClientError::Handshake(reason)) => assert!(reason.contains("Accept")),
+        res => panic!("unexpected {:?}", res.map(|_| ())),
+    }
+
+    for url in ["wss://example.com/", "http://example.com/", "ws:///"] {
+        assert!(matches!(
+            WsClient::connect(url).await,
+            Err(WsClientError::InvalidUrl)
+        ));
+    }
+
+    server.await.unwrap();
+}
+
+#[test]
+fn ws_client_codec_rejects_masked_frames() {
+    let mut buf = BytesMut::new();
+    Ws::new()
+        .encode(WsFrame::builder().masked().text("a"), &mut buf)
+        .unwrap();
+    assert!(Ws::client().decode(&mut buf).is_err());
+
+    let mut buf = BytesMut::new();
+    Ws::new()
+        .encode(WsFrame::builder().text("a"), &mut buf)
+        .unwrap();
+    assert_eq!(Ws::client().decode(&mut buf).unwrap().unwrap().data(), b"a");
+}
@@ -0,0 +1,93 @@
+use bytes::BytesMut;
+use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAYS: [&[u8; 3]; 7] = [b"Sun", b"Mon", b"Tue", b"Wed", b"Thu", b"Fri", b"Sat"];
+const MONTHS: [&[u8; 3]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+thread_local! {
+    // each worker runs on its own thread, so this is a per-worker cache
+    static CACHED: RefCell<CachedDate> = RefCell::new(CachedDate::new());
+}
+
+struct CachedDate {
+    secs: u64,
+    bytes: [u8; 29],
+}
+
+impl CachedDate {
+    fn new() -> Self {
+        let mut date = Self {
+            secs: 0,
+            bytes: [0; 29],
+        };
+        date.update(now());
+        date
+    }
+
+    fn update(&mut self, secs: u64) {
+        self.secs = secs;
+        self.bytes = format(secs);
+    }
+}
+
+/// Appends the current time as an IMF-fixdate, e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`. The formatted value is only rebuilt
+/// when the second changes.
+pub(crate) fn extend(dest: &mut BytesMut) {
+    CACHED.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        let secs = now();
+
+        if secs != cached.secs {
+            cached.update(secs);
+        }
+
+        dest.extend_from_slice(&cached.bytes);
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub(crate) fn format(secs: u64) -> [u8; 29] {
+    let days = secs / 86400;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+
+    let mut buf = *b"Thu, 01 Jan 1970 00:00:00 GMT";
+    buf[..3].copy_from_slice(DAYS[((days + 4) % 7) as usize]);
+    two_digits(&mut buf[5..7], day);
+    buf[8..11].copy_from_slice(MONTHS[month as usize - 1]);
+    two_digits(&mut buf[12..14], (year / 100) as u32);
+    two_digits(&mut buf[14..16], (year % 100) as u32);
+    two_digits(&mut buf[17..19], (rem / 3600) as u32);
+    two_digits(&mut buf[20..22], (rem % 3600 / 60) as u32);
+    two_digits(&mut buf[23..25], (rem % 60) as u32);
+    buf
+}
+
+fn two_digits(dest: &mut [u8], n: u32) {
+    dest[0] = b'0' + (n / 10) as u8;
+    dest[1] = b'0' + (n % 10) as u8;
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,269 @@
+use crate::codec::websocket::WsError;
+use bytes::BytesMut;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+const EXTENSION: &str = "permessage-deflate";
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Server settings for the RFC 7692 `permessage-deflate` extension.
+///
+/// The deflate backend always compresses with a 32K window, so offers that
+/// limit `server_max_window_bits` below 15 are declined. Any
+/// `client_max_window_bits` is accepted since inflating with a larger
+/// window is always possible.
+#[derive(Debug, Clone)]
+pub struct Deflate {
+    level: u32,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl Deflate {
+    pub fn new() -> Self {
+        Self {
+            level: Compression::default().level(),
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        }
+    }
+
+    /// Compression level from 0 to 9, defaults to 6.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+
+    /// Resets the compressor after every message, trading compression ratio
+    /// for the memory otherwise kept per connection.
+    pub fn server_no_context_takeover(mut self) -> Self {
+        self.server_no_context_takeover = true;
+        self
+    }
+
+    /// Asks clients to reset their compressor after every message.
+    pub fn client_no_context_takeover(mut self) -> Self {
+        self.client_no_context_takeover = true;
+        self
+    }
+
+    /// Picks the first acceptable offer from a `Sec-WebSocket-Extensions`
+    /// header, returning the negotiated parameters and the response value.
+    pub(crate) fn negotiate(&self, header: &str) -> Option<(DeflateParams, String)> {
+        header.split(',').find_map(|offer| self.accept(offer))
+    }
+
+    fn accept(&self, offer: &str) -> Option<(DeflateParams, String)> {
+        let mut parts = offer.split(';').map(str::trim);
+
+        if !parts.next()?.eq_ignore_ascii_case(EXTENSION) {
+            return None;
+        }
+
+        let mut params = DeflateParams {
+            level: self.level,
+            server_no_context_takeover: self.server_no_context_takeover,
+            client_no_context_takeover: self.client_no_context_takeover,
+        };
+
+        let mut seen = Vec::new();
+        // an offered server window has to be echoed, even the default one
+        let mut server_max_window_bits = false;
+
+        for param in parts {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+
+            let name = name.to_ascii_lowercase();
+
+            if seen.contains(&name) {
+                return None;
+            }
+
+            match (name.as_str(), value) {
+                ("server_no_context_takeover", None) => {
+                    params.server_no_context_takeover = true;
+                }
+                ("client_no_context_takeover", None) => {
+                    params.client_no_context_takeover = true;
+                }
+                ("server_max_window_bits", Some(bits)) => {
+                    if window_bits(bits)? != 15 {
+                        return None;
+                    }
+                    server_max_window_bits = true;
+                }
+                ("client_max_window_bits", None) => {}
+                ("client_max_window_bits", Some(bits)) => {
+                    window_bits(bits)?;
+                }
+                _ => return None,
+            }
+
+            seen.push(name);
+        }
+
+        let mut response = String::from(EXTENSION);
+
+        if params.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+
+        if params.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+
+        if server_max_window_bits {
+            response.push_str("; server_max_window_bits=15");
+        }
+
+        Some((params, response))
+    }
+}
+
+impl Default for Deflate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn window_bits(value: &str) -> Option<u8> {
+    match value.parse() {
+        Ok(bits) if (8..=15).contains(&bits) => Some(bits),
+        _ => None,
+    }
+}
+
+/// The outcome of a successful negotiation for one connection.
+#[derive(Debug, Clone)]
+pub(crate) struct DeflateParams {
+    level: u32,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl DeflateParams {
+    pub(crate) fn deflater(&self) -> Deflater {
+        Deflater {
+            compress: Compress::new(Compression::new(self.level), false),
+            reset: self.server_no_context_takeover,
+        }
+    }
+
+    pub(crate) fn inflater(&self) -> Inflater {
+        Inflater {
+            decompress: Decompress::new(false),
+            reset: self.client_no_context_takeover,
+        }
+    }
+}
+
+pub(crate) struct Deflater {
+    compress: Compress,
+    reset: bool,
+}
+
+impl Deflater {
+    /// Compresses the next part of a message. The trailing empty block is
+    /// removed from the final part as required by RFC 7692.
+    pub(crate) fn compress(&mut self, data: &[u8], fin: bool) -> BytesMut {
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+
+            if out.capacity() - out.len() < 64 {
+                out.reserve((data.len() - consumed).max(1024));
+            }
+
+            let status = self
+                .compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .expect("deflate stream error");
+
+            let consumed = (self.compress.total_in() - start) as usize;
+
+            if consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+
+            if status == Status::StreamEnd {
+                break;
+            }
+        }
+
+        if fin {
+            if out.ends_with(&TAIL) {
+                out.truncate(out.len() - TAIL.len());
+            }
+
+            if self.reset {
+                self.compress.reset();
+            }
+        }
+
+        BytesMut::from(&out[..])
+    }
+}
+
+pub(crate) struct Inflater {
+    decompress: Decompress,
+    reset: bool,
+}
+
+impl Inflater {
+    /// Decompresses a complete message, failing once the output would exceed
+    /// `limit` bytes.
+    pub(crate) fn decompress(&mut self, data: &[u8], limit: usize) -> Result<BytesMut, WsError> {
+        let mut input = Vec::with_capacity(data.len() + TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&TAIL);
+
+        // one byte past the limit tells an exact fit from an overflow
+        let cap = limit.saturating_add(1);
+        let mut out = Vec::with_capacity(data.len().saturating_mul(2).saturating_add(64).min(cap));
+        let start = self.decompress.total_in();
+
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+
+            if out.len() > limit {
+                return Err(WsError::Size);
+            }
+
+            if out.len() == out.capacity() {
+                // grow in steps, never far past the limit
+                out.reserve(out.capacity().max(1024).min(cap - out.len()));
+            }
+
+            let before = (self.decompress.total_in(), self.decompress.total_out());
+
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|_| WsError::Compression)?;
+
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let progress = (self.decompress.total_in(), self.decompress.total_out()) != before;
+
+            if consumed == input.len() && out.len() < out.capacity() {
+                break;
+            }
+
+            if !progress && out.len() < out.capacity() {
+                return Err(WsError::Compression);
+            }
+        }
+
+        if out.len() > limit {
+            return Err(WsError::Size);
+        }
+
+        if self.reset {
+            self.decompress.reset(false);
+        }
+
+        Ok(BytesMut::from(&out[..]))
+    }
+}
@@ -1,88 +1,305 @@
-use crate::codec::{Decoder, Encoder};
+use crate::codec::{date, Decoder, Encoder};
 use crate::context::Body;
-use bytes::BytesMut;
-use http::header::HeaderValue;
-use http::{Request, Response};
+use bytes::{Buf, BytesMut};
+use http::header::{AsHeaderName, HeaderMap, HeaderValue, CONTENT_LENGTH, DATE, TRANSFER_ENCODING};
+use http::{Request, Response, StatusCode, Version};
 use std::fmt::{self, Write};
 use std::marker::PhantomData;
 
 pub struct Http<T> {
+    head: Option<(Request<()>, Framing)>,
+    body: BytesMut,
+    max_body: usize,
     _marker: PhantomData<T>,
 }
 
+/// How the end of a request body is found.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Framing {
+    Length(usize),
+    Chunked(Chunk),
+}
+
+/// Where a chunked body is at, between calls to `decode_chunks`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Chunk {
+    Size,
+    Data(usize),
+    Trailers,
+}
+
+/// Why a request could not be decoded. The connection cannot be used any
+/// further, the request is answered with `status` and closed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HttpError {
+    /// The request is malformed.
+    BadRequest,
+    /// The request uses a transfer coding other than chunked.
+    NotImplemented,
+    /// The request body is larger than allowed.
+    PayloadTooLarge,
+}
+
+impl HttpError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest => f.write_str("malformed http request"),
+            Self::NotImplemented => f.write_str("unsupported transfer-encoding"),
+            Self::PayloadTooLarge => f.write_str("request body too large"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
 impl<T> Http<T> {
     pub fn new() -> Self {
         Http {
+            head: None,
+            body: BytesMut::new(),
+            max_body: usize::MAX,
             _marker: PhantomData,
         }
     }
+
+    /// Fails requests whose body is larger than `size`, as soon as their
+    /// headers or chunk sizes announce it. Unlimited by default.
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.max_body = size;
+    }
+
+    /// The head of a request that has been parsed but whose body has not
+    /// been fully received yet.
+    pub fn pending(&self) -> Option<&Request<()>> {
+        self.head.as_ref().map(|(head, _)| head)
+    }
 }
 
 impl Decoder for Http<Body> {
     type Item = Request<Body>;
-    type Error = ();
+    type Error = HttpError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() == 0 {
-            return Ok(None);
+        let (head, mut framing) = match self.head.take() {
+            Some(head) => head,
+            None => match decode_head(src)? {
+                Some(head) => head,
+                None => return Ok(None),
+            },
+        };
+
+        let body = match &mut framing {
+            Framing::Length(length) if src.len() >= *length => Some(src.split_to(*length)),
+            Framing::Length(_) => None,
+            Framing::Chunked(state) => match decode_chunks(&mut self.body, state, src) {
+                Ok(true) => Some(self.body.split()),
+                Ok(false) => None,
+                Err(()) => return Err(HttpError::BadRequest),
+            },
+        };
+
+        // what has been announced so far counts, so that no more of a body
+        // that is too large gets buffered
+        let announced = match (&body, framing) {
+            (Some(body), _) => body.len(),
+            (None, Framing::Length(length)) => length,
+            (None, Framing::Chunked(Chunk::Data(length))) => self.body.len().saturating_add(length),
+            (None, Framing::Chunked(_)) => self.body.len(),
+        };
+
+        if announced > self.max_body {
+            self.body.clear();
+            return Err(HttpError::PayloadTooLarge);
         }
 
-        let mut headers = [None; 32];
+        let body = match body {
+            Some(body) => body,
+            None => {
+                self.head = Some((head, framing));
+                return Ok(None);
+            }
+        };
 
-        let (method, path, version, amt) = {
-            let mut parsed = [httparse::EMPTY_HEADER; 32];
-            let mut r = httparse::Request::new(&mut parsed);
+        let body = if body.is_empty() {
+            Body::empty()
+        } else {
+            Body::from(&body)
+        };
 
-            let amt = match r.parse(src) {
-                Ok(httparse::Status::Complete(amt)) => amt,
-                Ok(httparse::Status::Partial) => return Ok(None),
-                e => {
-                    log::error!("codec http amt {:?}", e);
-                    return Err(());
-                }
-            };
+        Ok(Some(head.map(|_| body)))
+    }
+}
+
+fn decode_head(src: &mut BytesMut) -> Result<Option<(Request<()>, Framing)>, HttpError> {
+    if src.len() == 0 {
+        return Ok(None);
+    }
 
-            for (i, header) in r.headers.iter().enumerate() {
-                let k = header.name;
-                let v = header.value;
-                headers[i] = Some((k, v));
+    let mut headers = [None; 32];
+
+    let (method, path, version, amt) = {
+        let mut parsed = [httparse::EMPTY_HEADER; 32];
+        let mut r = httparse::Request::new(&mut parsed);
+
+        let amt = match r.parse(src) {
+            Ok(httparse::Status::Complete(amt)) => amt,
+            Ok(httparse::Status::Partial) => return Ok(None),
+            e => {
+                log::error!("codec http amt {:?}", e);
+                return Err(HttpError::BadRequest);
             }
-            (r.method.unwrap(), r.path.unwrap(), r.version.unwrap(), amt)
         };
 
-        if version != 1 {
-            return Err(());
+        for (i, header) in r.headers.iter().enumerate() {
+            let k = header.name;
+            let v = header.value;
+            headers[i] = Some((k, v));
         }
+        (r.method.unwrap(), r.path.unwrap(), r.version.unwrap(), amt)
+    };
+
+    let version = match version {
+        0 => Version::HTTP_10,
+        1 => Version::HTTP_11,
+        _ => return Err(HttpError::BadRequest),
+    };
 
-        let mut builder = Request::builder()
-            .method(method)
-            .uri(path)
-            .version(http::Version::HTTP_11);
+    let mut builder = Request::builder().method(method).uri(path).version(version);
+
+    for header in headers.iter() {
+        let (k, v) = match *header {
+            Some((k, v)) => (k, v),
+            None => break,
+        };
 
-        for header in headers.iter() {
-            let (k, v) = match *header {
-                Some((k, v)) => (k, v),
-                None => break,
-            };
+        let value = HeaderValue::from_bytes(v).map_err(|_| HttpError::BadRequest)?;
+        builder = builder.header(k, value);
+    }
+
+    let head = builder.body(()).map_err(|_| HttpError::BadRequest)?;
+
+    // a body framed in more than one way could be read differently by a
+    // proxy in front of us, so such requests are refused outright
+    let mut lengths = head.headers().get_all(CONTENT_LENGTH).iter();
+    let length = lengths.next();
+    if lengths.next().is_some()
+        || (length.is_some() && head.headers().contains_key(TRANSFER_ENCODING))
+    {
+        log::error!("codec http ambiguous request framing");
+        return Err(HttpError::BadRequest);
+    }
 
-            let value = HeaderValue::from_bytes(v).unwrap();
-            builder = builder.header(k, value);
+    // only chunked on its own is understood
+    let mut codings = head.headers().get_all(TRANSFER_ENCODING).iter();
+    let chunked = |te: &HeaderValue| te.as_bytes().eq_ignore_ascii_case(b"chunked");
+
+    let framing = match (codings.next(), codings.next()) {
+        (Some(te), None) if chunked(te) => Framing::Chunked(Chunk::Size),
+        (Some(_), _) => {
+            log::error!("codec http transfer-encoding is not supported");
+            return Err(HttpError::NotImplemented);
         }
+        (None, _) => match length {
+            Some(v) => v
+                .to_str()
+                .ok()
+                .map(str::trim)
+                .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|v| v.parse().ok())
+                .map(Framing::Length)
+                .ok_or(HttpError::BadRequest)?,
+            None => Framing::Length(0),
+        },
+    };
+
+    let _ = src.split_to(amt);
+
+    Ok(Some((head, framing)))
+}
 
-        let _ = src.split_to(amt);
-        let req = builder.body(Body::from(src)).unwrap();
+/// Reads as much of a chunked body as is available into `body`, returning
+/// true once the last chunk and the trailers have been read. Trailers and
+/// chunk extensions are discarded.
+pub(crate) fn decode_chunks(
+    body: &mut BytesMut,
+    state: &mut Chunk,
+    src: &mut BytesMut,
+) -> Result<bool, ()> {
+    loop {
+        match *state {
+            Chunk::Size => {
+                let line = match take_line(src)? {
+                    Some(line) => line,
+                    None => return Ok(false),
+                };
+
+                let size = line[..].split(|b| *b == b';').next().unwrap_or(&[]);
+                let size = std::str::from_utf8(size).map_err(|_| ())?;
+                let size = usize::from_str_radix(size.trim(), 16).map_err(|_| ())?;
+
+                *state = match size {
+                    0 => Chunk::Trailers,
+                    size => Chunk::Data(size),
+                };
+            }
+            Chunk::Data(size) => {
+                if src.len() < size.saturating_add(2) {
+                    return Ok(false);
+                }
 
-        src.clear();
+                if &src[size..size + 2] != b"\r\n" {
+                    return Err(());
+                }
 
-        Ok(Some(req))
+                body.extend_from_slice(&src[..size]);
+                src.advance(size + 2);
+                *state = Chunk::Size;
+            }
+            Chunk::Trailers => match take_line(src)? {
+                Some(line) if line.is_empty() => return Ok(true),
+                Some(_) => {}
+                None => return Ok(false),
+            },
+        }
     }
 }
 
+/// Takes a CRLF terminated line off `src`, without the line ending.
+fn take_line(src: &mut BytesMut) -> Result<Option<BytesMut>, ()> {
+    let end = match src.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        // a chunk size or trailer line never needs this much
+        None if src.len() > 8192 => return Err(()),
+        None => return Ok(None),
+    };
+
+    let line = src.split_to(end);
+    src.advance(2);
+    Ok(Some(line))
+}
+
 impl<T> Encoder<Response<()>> for Http<T> {
     type Error = ();
 
     fn encode(&mut self, item: Response<()>, dest: &mut BytesMut) -> Result<(), Self::Error> {
-        write!(ByteWriter(dest), "HTTP/1.1 {}\r\n", item.status(),).unwrap();
+        write!(
+            ByteWriter(dest),
+            "{:?} {}\r\n",
+            item.version(),
+            item.status()
+        )
+        .unwrap();
+        write_date(item.headers(), dest);
 
         for (k, v) in item.headers() {
             dest.extend_from_slice(k.as_str().as_bytes());
@@ -103,11 +320,13 @@ impl Encoder<Response<Body>> for Http<Body> {
     fn encode(&mut self, item: Response<Body>, dest: &mut BytesMut) -> Result<(), Self::Error> {
         write!(
             ByteWriter(dest),
-            "HTTP/1.1 {}\r\ncontent-length: {}\r\n",
+            "{:?} {}\r\ncontent-length: {}\r\n",
+            item.version(),
             item.status(),
             item.body().len(),
         )
         .unwrap();
+        write_date(item.headers(), dest);
 
         for (k, v) in item.headers() {
             dest.extend_from_slice(k.as_str().as_bytes());
@@ -123,7 +342,26 @@ impl Encoder<Response<Body>> for Http<Body> {
     }
 }
 
-struct ByteWriter<'a>(&'a mut BytesMut);
+fn write_date(headers: &HeaderMap, dest: &mut BytesMut) {
+    if !headers.contains_key(DATE) {
+        dest.extend_from_slice(b"date: ");
+        date::extend(dest);
+        dest.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Returns true if the comma separated header `name` contains `token`,
+/// compared case-insensitively, e.g. `keep-alive` in `Connection`.
+pub(crate) fn has_token<K: AsHeaderName>(headers: &HeaderMap, name: K, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case(token))
+}
+
+pub(crate) struct ByteWriter<'a>(pub(crate) &'a mut BytesMut);
 
 impl fmt::Write for ByteWriter<'_> {
     fn write_str(&mut self, s: &str) -> std::fmt::Result {
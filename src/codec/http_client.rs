@@ -0,0 +1,259 @@
+use crate::codec::{
+    http::{decode_chunks, ByteWriter, Chunk},
+    Decoder, Encoder,
+};
+use crate::context::Body;
+use bytes::{Buf, BytesMut};
+use http::header::{HeaderValue, CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::{Method, Request, Response, StatusCode, Version};
+use std::fmt::Write;
+
+/// The client side of HTTP/1.1: encodes requests and decodes the responses
+/// to them, one at a time.
+pub struct HttpClient {
+    head: Option<(Response<()>, Framing)>,
+    body: BytesMut,
+    /// Whether the request in flight is a `HEAD`, whose response has no
+    /// body whatever its headers say.
+    head_request: bool,
+    max_body: usize,
+}
+
+/// How the end of a response body is found.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Framing {
+    Length(usize),
+    Chunked(Chunk),
+    /// The body ends when the server closes the connection.
+    Close,
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self {
+            head: None,
+            body: BytesMut::new(),
+            head_request: false,
+            max_body: usize::MAX,
+        }
+    }
+
+    /// Fails responses whose body is larger than `size`, as soon as their
+    /// headers or chunk sizes announce it. Unlimited by default.
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.max_body = size;
+    }
+
+    /// Decodes the next response as the answer to a `HEAD` request, which
+    /// `encode` does on its own.
+    pub fn set_head_request(&mut self, head: bool) {
+        self.head_request = head;
+    }
+
+    /// Completes a response whose body is delimited by the end of the
+    /// connection, once it has been closed. Fails if the connection closed
+    /// in the middle of a response of another kind.
+    pub fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Response<Body>>, ()> {
+        if let Some(resp) = self.decode(src)? {
+            return Ok(Some(resp));
+        }
+
+        match self.head.take() {
+            Some((head, Framing::Close)) => {
+                self.body.extend_from_slice(src);
+                src.clear();
+
+                if self.body.len() > self.max_body {
+                    return Err(());
+                }
+
+                Ok(Some(self.finish(head)))
+            }
+            Some(_) => Err(()),
+            None if src.is_empty() => Ok(None),
+            None => Err(()),
+        }
+    }
+
+    fn finish(&mut self, head: Response<()>) -> Response<Body> {
+        let body = self.body.split();
+
+        let body = if body.is_empty() {
+            Body::empty()
+        } else {
+            Body::from(&body)
+        };
+
+        head.map(|_| body)
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for HttpClient {
+    type Item = Response<Body>;
+    type Error = ();
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (head, mut framing) = match self.head.take() {
+            Some(head) => head,
+            None => match decode_head(src, self.head_request)? {
+                Some(head) => head,
+                None => return Ok(None),
+            },
+        };
+
+        let done = match &mut framing {
+            Framing::Length(length) => {
+                let n = (*length).min(src.len());
+                self.body.extend_from_slice(&src[..n]);
+                src.advance(n);
+                *length -= n;
+                *length == 0
+            }
+            Framing::Chunked(state) => decode_chunks(&mut self.body, state, src)?,
+            Framing::Close => {
+                self.body.extend_from_slice(src);
+                src.clear();
+                false
+            }
+        };
+
+        // what is still to come counts too, so that the body is never
+        // buffered past the limit
+        let pending = match framing {
+            Framing::Length(length) | Framing::Chunked(Chunk::Data(length)) => length,
+            _ => 0,
+        };
+
+        if self.body.len().saturating_add(pending) > self.max_body {
+            self.body.clear();
+            return Err(());
+        }
+
+        if !done {
+            self.head = Some((head, framing));
+            return Ok(None);
+        }
+
+        Ok(Some(self.finish(head)))
+    }
+}
+
+/// Parses a response head, skipping interim `1xx` responses.
+fn decode_head(
+    src: &mut BytesMut,
+    head_request: bool,
+) -> Result<Option<(Response<()>, Framing)>, ()> {
+    loop {
+        let (head, amt) = {
+            let mut parsed = [httparse::EMPTY_HEADER; 64];
+            let mut r = httparse::Response::new(&mut parsed);
+
+            let amt = match r.parse(src) {
+                Ok(httparse::Status::Complete(amt)) => amt,
+                Ok(httparse::Status::Partial) => return Ok(None),
+                Err(_) => return Err(()),
+            };
+
+            let version = match r.version {
+                Some(0) => Version::HTTP_10,
+                Some(1) => Version::HTTP_11,
+                _ => return Err(()),
+            };
+
+            let status = r.code.ok_or(())?;
+            let mut builder = Response::builder().status(status).version(version);
+
+            for header in r.headers.iter() {
+                let value = HeaderValue::from_bytes(header.value).map_err(|_| ())?;
+                builder = builder.header(header.name, value);
+            }
+
+            (builder.body(()).map_err(|_| ())?, amt)
+        };
+
+        src.advance(amt);
+
+        let status = head.status();
+
+        if status.is_informational() && status != StatusCode::SWITCHING_PROTOCOLS {
+            continue;
+        }
+
+        let headers = head.headers();
+
+        let framing = if head_request
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            Framing::Length(0)
+        } else if let Some(te) = headers.get(TRANSFER_ENCODING) {
+            // chunked must come last, anything else is delimited by the end
+            // of the connection
+            let te = te.to_str().map_err(|_| ())?;
+            match te.rsplit(',').next() {
+                Some(last) if last.trim().eq_ignore_ascii_case("chunked") => {
+                    Framing::Chunked(Chunk::Size)
+                }
+                _ => Framing::Close,
+            }
+        } else if let Some(length) = headers.get(CONTENT_LENGTH) {
+            let length = length
+                .to_str()
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .ok_or(())?;
+            Framing::Length(length)
+        } else {
+            Framing::Close
+        };
+
+        return Ok(Some((head, framing)));
+    }
+}
+
+impl Encoder<Request<Body>> for HttpClient {
+    type Error = ();
+
+    /// Writes the request in origin form, the caller is responsible for the
+    /// `Host` header.
+    fn encode(&mut self, item: Request<Body>, dest: &mut BytesMut) -> Result<(), Self::Error> {
+        self.head_request = item.method() == Method::HEAD;
+
+        let path = item.uri().path_and_query().map_or("/", |p| p.as_str());
+        write!(
+            ByteWriter(dest),
+            "{} {} {:?}\r\n",
+            item.method(),
+            path,
+            item.version()
+        )
+        .unwrap();
+
+        let len = item.body().len();
+        let sends_body =
+            len > 0 || matches!(*item.method(), Method::POST | Method::PUT | Method::PATCH);
+
+        if sends_body && !item.headers().contains_key(CONTENT_LENGTH) {
+            write!(ByteWriter(dest), "content-length: {}\r\n", len).unwrap();
+        }
+
+        for (k, v) in item.headers() {
+            dest.extend_from_slice(k.as_str().as_bytes());
+            dest.extend_from_slice(b": ");
+            dest.extend_from_slice(v.as_bytes());
+            dest.extend_from_slice(b"\r\n");
+        }
+
+        dest.extend_from_slice(b"\r\n");
+        item.body().bytes(dest);
+
+        Ok(())
+    }
+}
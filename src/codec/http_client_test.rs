@@ -0,0 +1,122 @@
+use crate::codec::{http_client::HttpClient, Decoder, Encoder};
+use crate::context::Body;
+use bytes::BytesMut;
+use http::{Method, Request, StatusCode};
+
+fn body(resp: &http::Response<Body>) -> BytesMut {
+    let mut out = BytesMut::new();
+    resp.body().bytes(&mut out);
+    out
+}
+
+#[test]
+fn content_length_response() {
+    let mut codec = HttpClient::new();
+    let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhe"[..]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(b"lloHTTP/1.1");
+    let resp = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&body(&resp)[..], b"hello");
+    assert_eq!(&buf[..], b"HTTP/1.1");
+}
+
+#[test]
+fn chunked_response() {
+    let mut codec = HttpClient::new();
+    let mut buf = BytesMut::from(
+        &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n3;ext=1\r\nabc\r\n"[..],
+    );
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(b"A\r\n0123456789\r\n0\r\nX-Trailer: 1\r\n\r\n");
+    let resp = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(&body(&resp)[..], b"abc0123456789");
+    assert!(buf.is_empty());
+
+    let mut buf =
+        BytesMut::from(&b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcde"[..]);
+    assert!(HttpClient::new().decode(&mut buf).is_err());
+}
+
+#[test]
+fn response_size_limit() {
+    // announced by the content length
+    let mut codec = HttpClient::new();
+    codec.set_max_body_size(4);
+    let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..]);
+    assert!(codec.decode(&mut buf).is_err());
+
+    // or by a chunk size, before its data has arrived
+    let mut codec = HttpClient::new();
+    codec.set_max_body_size(4);
+    let mut buf = BytesMut::from(
+        &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n3\r\n"[..],
+    );
+    assert!(codec.decode(&mut buf).is_err());
+
+    // or only by the data of a close delimited body
+    let mut codec = HttpClient::new();
+    codec.set_max_body_size(4);
+    let mut buf = BytesMut::from(&b"HTTP/1.0 200 OK\r\n\r\nabcd"[..]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b"e");
+    assert!(codec.decode_eof(&mut buf).is_err());
+}
+
+#[test]
+fn close_delimited_response() {
+    let mut codec = HttpClient::new();
+    let mut buf = BytesMut::from(&b"HTTP/1.0 200 OK\r\n\r\nsome"[..]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(b" body");
+    let resp = codec.decode_eof(&mut buf).unwrap().unwrap();
+    assert_eq!(&body(&resp)[..], b"some body");
+
+    // cut short
+    let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nab"[..]);
+    assert!(HttpClient::new().decode_eof(&mut buf).is_err());
+}
+
+#[test]
+fn bodiless_responses() {
+    let mut codec = HttpClient::new();
+    let mut buf = BytesMut::from(
+        &b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\nHTTP/1.1 200 OK\r\n\r\n"[..],
+    );
+    let resp = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..]);
+    codec.set_head_request(true);
+    let resp = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(resp.headers()["content-length"], "5");
+    assert_eq!(resp.body().len(), 0);
+}
+
+#[test]
+fn encode_request() {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri("http://localhost:8080/a?b=c")
+        .header("host", "localhost:8080")
+        .body::<Body>("hi".into())
+        .unwrap();
+
+    let mut out = BytesMut::new();
+    HttpClient::new().encode(req, &mut out).unwrap();
+    assert_eq!(
+        &out[..],
+        &b"POST /a?b=c HTTP/1.1\r\ncontent-length: 2\r\nhost: localhost:8080\r\n\r\nhi"[..]
+    );
+
+    let req = Request::post("/").body(Body::empty()).unwrap();
+    let mut out = BytesMut::new();
+    HttpClient::new().encode(req, &mut out).unwrap();
+    assert_eq!(
+        &out[..],
+        &b"POST / HTTP/1.1\r\ncontent-length: 0\r\n\r\n"[..]
+    );
+}
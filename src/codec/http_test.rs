@@ -4,3 +4,154 @@ fn http_test() {
     let uri = "/api/todos?a=b".parse::<Uri>().unwrap();
     println!("{:?}", uri.path());
 }
+
+#[test]
+fn http_10_request() {
+    use crate::codec::{http::Http, Decoder, Encoder};
+    use crate::context::Body;
+    use bytes::BytesMut;
+    use http::{Response, Version};
+
+    let mut buf = BytesMut::from(&b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"[..]);
+    let req = Http::<Body>::new().decode(&mut buf).unwrap().unwrap();
+    assert_eq!(req.version(), Version::HTTP_10);
+    assert!(super::http::has_token(
+        req.headers(),
+        "connection",
+        "keep-alive"
+    ));
+
+    let mut resp: Response<Body> = Response::new("ok".into());
+    *resp.version_mut() = req.version();
+
+    let mut out = BytesMut::new();
+    Http::<Body>::new().encode(resp, &mut out).unwrap();
+    assert!(out.starts_with(b"HTTP/1.0 200 OK\r\n"));
+}
+
+#[test]
+fn http_body_framing() {
+    use crate::codec::{http::Http, Decoder};
+    use crate::context::Body;
+    use bytes::BytesMut;
+
+    let mut codec = Http::<Body>::new();
+    let mut buf = BytesMut::from(
+        &b"POST /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nab"[..],
+    );
+
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    assert!(codec.pending().unwrap().headers().contains_key("expect"));
+
+    buf.extend_from_slice(b"cdeGET /b HTTP/1.1\r\n\r\n");
+    let req = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(req.body().len(), 5);
+    assert!(codec.pending().is_none());
+
+    let req = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(req.uri().path(), "/b");
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn http_chunked_request() {
+    use crate::codec::{
+        http::{Http, HttpError},
+        Decoder,
+    };
+    use crate::context::Body;
+    use bytes::BytesMut;
+
+    let mut codec = Http::<Body>::new();
+    let mut buf = BytesMut::from(
+        &b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r\n"[..],
+    );
+
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+    assert!(codec.pending().is_some());
+
+    buf.extend_from_slice(b"2\r\nde\r\n0\r\nX-Sum: 1\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+    let req = codec.decode(&mut buf).unwrap().unwrap();
+    let mut body = BytesMut::new();
+    req.body().bytes(&mut body);
+    assert_eq!(&body[..], b"abcde");
+
+    let req = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(req.uri().path(), "/b");
+
+    let mut buf =
+        BytesMut::from(&b"POST /a HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"[..]);
+    let err = Http::<Body>::new().decode(&mut buf).unwrap_err();
+    assert_eq!(err, HttpError::NotImplemented);
+    assert_eq!(err.status(), http::StatusCode::NOT_IMPLEMENTED);
+}
+
+#[test]
+fn http_rejects_ambiguous_framing() {
+    use crate::codec::{
+        http::{Http, HttpError},
+        Decoder,
+    };
+    use crate::context::Body;
+    use bytes::BytesMut;
+
+    let cases: [&[u8]; 5] = [
+        b"POST /a HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd",
+        b"POST /a HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc",
+        b"POST /a HTTP/1.1\r\nContent-Length: 3, 3\r\n\r\nabc",
+        b"POST /a HTTP/1.1\r\nContent-Length: +3\r\n\r\nabc",
+        b"POST /a HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+    ];
+
+    for case in cases {
+        let mut buf = BytesMut::from(case);
+        let err = Http::<Body>::new().decode(&mut buf).unwrap_err();
+        assert_eq!(err, HttpError::BadRequest);
+        assert_eq!(err.status(), http::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[test]
+fn http_body_size_limit() {
+    use crate::codec::{
+        http::{Http, HttpError},
+        Decoder,
+    };
+    use crate::context::Body;
+    use bytes::BytesMut;
+
+    // refused from the headers, before any of the body arrived
+    let mut codec = Http::<Body>::new();
+    codec.set_max_body_size(8);
+    let mut buf = BytesMut::from(&b"POST /a HTTP/1.1\r\nContent-Length: 9\r\n\r\n"[..]);
+    let err = codec.decode(&mut buf).unwrap_err();
+    assert_eq!(err, HttpError::PayloadTooLarge);
+    assert_eq!(err.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+
+    // chunks that fit on their own but not together
+    let mut codec = Http::<Body>::new();
+    codec.set_max_body_size(8);
+    let mut buf = BytesMut::from(
+        &b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nabcde\r\n"[..],
+    );
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(b"4\r\n");
+    let err = codec.decode(&mut buf).unwrap_err();
+    assert_eq!(err, HttpError::PayloadTooLarge);
+
+    // a body of exactly the limit is fine
+    let mut codec = Http::<Body>::new();
+    codec.set_max_body_size(8);
+    let mut buf = BytesMut::from(&b"POST /a HTTP/1.1\r\nContent-Length: 8\r\n\r\nabcdefgh"[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap().unwrap().body().len(), 8);
+}
+
+#[test]
+fn http_date_format() {
+    use crate::codec::date::format;
+
+    assert_eq!(&format(0), b"Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(&format(784111777), b"Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(&format(951782400), b"Tue, 29 Feb 2000 00:00:00 GMT");
+}
@@ -1,8 +1,18 @@
 mod codec;
+mod date;
+
+pub(crate) mod deflate;
 
 pub(crate) mod http;
+pub(crate) mod http_client;
 pub(crate) mod websocket;
 pub(crate) use codec::{Decoder, Encoder};
 
+#[cfg(test)]
+mod http_client_test;
+
 #[cfg(test)]
 mod http_test;
+
+#[cfg(test)]
+mod websocket_test;
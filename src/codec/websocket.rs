@@ -1,16 +1,27 @@
+use crate::codec::deflate::{Deflater, Inflater};
 use crate::codec::{Decoder, Encoder};
 use crate::context::Body;
 use byteorder::{BigEndian, ReadBytesExt};
 use bytes::{BufMut, BytesMut};
 use rand::prelude::*;
+use std::fmt;
 use std::io::Cursor;
 
 const FIN: u8 = 0x80;
+const RSV: u8 = 0x70;
+const RSV1: u8 = 0x40;
+const OPCODE: u8 = 0x0F;
 const MASK: u8 = 0x80;
-// const FRAME_LIMIT: usize = 65535;
+pub(crate) const MAX_FRAME: usize = 16 << 20;
+pub(crate) const MAX_MESSAGE: usize = 64 << 20;
+/// How much room is made for a payload that has not fully arrived yet. The
+/// buffer grows as the data comes in rather than trusting the header.
+const READ_AHEAD: usize = 8 << 10;
 
 #[derive(Debug)]
 pub struct WsFrame {
+    fin: bool,
+    rsv1: bool,
     opcode: Opcode,
     pub masked: bool,
     data: BytesMut,
@@ -21,10 +32,21 @@ impl WsFrame {
         WsFrameBuilder::new()
     }
 
+    /// Whether this is the final fragment of a message.
+    pub fn fin(&self) -> bool {
+        self.fin
+    }
+
     pub fn opcode(&self) -> &Opcode {
         &self.opcode
     }
 
+    /// Whether the RSV1 bit is set, marking the first frame of a message
+    /// compressed with permessage-deflate.
+    pub fn compressed(&self) -> bool {
+        self.rsv1
+    }
+
     pub fn masked(&self) -> bool {
         self.masked
     }
@@ -40,15 +62,27 @@ impl WsFrame {
     pub fn into_parts(self) -> (Opcode, bool, BytesMut) {
         (self.opcode, self.masked, self.data)
     }
+
+    /// Compresses the payload of a data frame, marking the first frame of
+    /// the message with RSV1.
+    pub(crate) fn compress(mut self, deflater: &mut Deflater) -> WsFrame {
+        self.data = deflater.compress(&self.data, self.fin);
+        self.rsv1 = self.opcode != Opcode::CONTINUATION;
+        self
+    }
 }
 
 pub struct WsFrameBuilder {
+    fin: bool,
     masked: bool,
 }
 
 impl WsFrameBuilder {
     pub fn new() -> Self {
-        Self { masked: false }
+        Self {
+            fin: true,
+            masked: false,
+        }
     }
 }
 
@@ -58,56 +92,318 @@ impl WsFrameBuilder {
         self
     }
 
+    /// Marks the frame as a non-final fragment of a message.
+    pub fn fragment(mut self) -> Self {
+        self.fin = false;
+        self
+    }
+
     pub fn close(self) -> WsFrame {
-        WsFrame {
-            opcode: Opcode::CLOSE,
-            masked: false,
-            data: BytesMut::new(),
-        }
+        self.frame(Opcode::CLOSE, BytesMut::new())
+    }
+
+    /// Close frame carrying a status code and reason. The reason is
+    /// truncated to fit the 125 byte control frame limit.
+    pub fn close_with(self, code: CloseCode, reason: &str) -> WsFrame {
+        self.frame(Opcode::CLOSE, close_payload(code, reason))
     }
 
     pub fn continuation(self, fragment: impl Into<Body>) -> WsFrame {
-        Self::body(Opcode::CONTINUATION, Some(fragment.into()))
+        self.body(Opcode::CONTINUATION, Some(fragment.into()))
     }
 
     pub fn binary(self, fragment: impl Into<Body>) -> WsFrame {
-        Self::body(Opcode::BINARY, Some(fragment.into()))
+        self.body(Opcode::BINARY, Some(fragment.into()))
     }
 
     pub fn text(self, fragment: impl Into<Body>) -> WsFrame {
-        Self::body(Opcode::TEXT, Some(fragment.into()))
+        self.body(Opcode::TEXT, Some(fragment.into()))
     }
 
     pub fn ping(self) -> WsFrame {
-        Self::body(Opcode::PING, None)
+        self.body(Opcode::PING, None)
     }
 
     pub fn pong(self) -> WsFrame {
-        Self::body(Opcode::PONG, None)
+        self.body(Opcode::PONG, None)
+    }
+
+    /// Pong echoing the payload of the ping it answers.
+    pub fn pong_with(self, data: &[u8]) -> WsFrame {
+        self.frame(Opcode::PONG, BytesMut::from(data))
     }
 
-    fn body(opcode: Opcode, body: Option<Body>) -> WsFrame {
+    pub(crate) fn body(self, opcode: Opcode, body: Option<Body>) -> WsFrame {
         let mut data = BytesMut::new();
 
         if let Some(xs) = body {
             xs.as_bytes(&mut data);
         }
 
+        self.frame(opcode, data)
+    }
+
+    fn frame(self, opcode: Opcode, data: BytesMut) -> WsFrame {
         WsFrame {
+            fin: self.fin,
+            rsv1: false,
             opcode,
-            masked: false,
+            masked: self.masked,
             data,
         }
     }
 }
 
-pub struct Ws {
-    opcode: Option<Opcode>,
-    length: usize,
-    data: Option<BytesMut>,
+/// A complete websocket message, reassembled from its fragments.
+#[derive(Debug, Clone)]
+pub struct Message {
+    opcode: Opcode,
+    data: BytesMut,
+}
+
+impl Message {
+    pub fn text(data: impl Into<Body>) -> Self {
+        Self::new(Opcode::TEXT, data.into())
+    }
+
+    pub fn binary(data: impl Into<Body>) -> Self {
+        Self::new(Opcode::BINARY, data.into())
+    }
+
+    pub fn ping(data: impl Into<Body>) -> Self {
+        Self::new(Opcode::PING, data.into())
+    }
+
+    pub fn pong(data: impl Into<Body>) -> Self {
+        Self::new(Opcode::PONG, data.into())
+    }
+
+    pub fn close(code: CloseCode, reason: &str) -> Self {
+        Self {
+            opcode: Opcode::CLOSE,
+            data: close_payload(code, reason),
+        }
+    }
+
+    fn new(opcode: Opcode, body: Body) -> Self {
+        let mut data = BytesMut::with_capacity(body.len());
+        body.as_bytes(&mut data);
+        Self { opcode, data }
+    }
+
+    pub fn opcode(&self) -> &Opcode {
+        &self.opcode
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> BytesMut {
+        self.data
+    }
+
+    /// The payload of a text message. Received text is always valid UTF-8,
+    /// so this only returns `None` for other kinds of messages.
+    pub fn as_str(&self) -> Option<&str> {
+        match self.opcode {
+            Opcode::TEXT => std::str::from_utf8(&self.data).ok(),
+            _ => None,
+        }
+    }
+
+    /// Like `as_str`, taking ownership of the payload.
+    pub fn into_string(self) -> Option<String> {
+        match self.opcode {
+            Opcode::TEXT => String::from_utf8(self.data.to_vec()).ok(),
+            _ => None,
+        }
+    }
+
+    /// The status code and reason of a close message.
+    pub fn close_frame(&self) -> Option<CloseFrame> {
+        match self.opcode {
+            Opcode::CLOSE => CloseFrame::parse(&self.data).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Status codes sent in close frames, see RFC 6455 section 7.4.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CloseCode {
+    /// 1000, the purpose of the connection has been fulfilled.
+    Normal,
+    /// 1001, the server is going down or the client navigated away.
+    Away,
+    /// 1002, the peer violated the protocol.
+    Protocol,
+    /// 1003, a message of a type that cannot be accepted was received.
+    Unsupported,
+    /// 1005, the close frame did not carry a status code. Never sent.
+    Status,
+    /// 1006, the connection dropped without a close frame. Never sent.
+    Abnormal,
+    /// 1007, a message contained data inconsistent with its type.
+    Invalid,
+    /// 1008, a message violated the endpoint's policy.
+    Policy,
+    /// 1009, a message was too big to process.
+    Size,
+    /// 1010, the client expected an extension the server did not negotiate.
+    Extension,
+    /// 1011, the server hit an unexpected condition.
+    Error,
+    /// 1012, the server is restarting.
+    Restart,
+    /// 1013, the server is overloaded, try again later.
+    Again,
+    /// Registered (3000-3999) or application defined (4000-4999) codes.
+    Other(u16),
+}
+
+impl CloseCode {
+    /// Whether the code may appear in a close frame on the wire.
+    pub fn is_allowed(&self) -> bool {
+        match self {
+            Self::Status | Self::Abnormal => false,
+            Self::Other(code) => (3000..5000).contains(code),
+            _ => true,
+        }
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1001 => Self::Away,
+            1002 => Self::Protocol,
+            1003 => Self::Unsupported,
+            1005 => Self::Status,
+            1006 => Self::Abnormal,
+            1007 => Self::Invalid,
+            1008 => Self::Policy,
+            1009 => Self::Size,
+            1010 => Self::Extension,
+            1011 => Self::Error,
+            1012 => Self::Restart,
+            1013 => Self::Again,
+            code => Self::Other(code),
+        }
+    }
 }
 
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> u16 {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::Away => 1001,
+            CloseCode::Protocol => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::Status => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::Invalid => 1007,
+            CloseCode::Policy => 1008,
+            CloseCode::Size => 1009,
+            CloseCode::Extension => 1010,
+            CloseCode::Error => 1011,
+            CloseCode::Restart => 1012,
+            CloseCode::Again => 1013,
+            CloseCode::Other(code) => code,
+        }
+    }
+}
+
+/// The status code and reason carried by a close frame.
 #[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CloseFrame {
+    code: CloseCode,
+    reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: CloseCode, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn code(&self) -> CloseCode {
+        self.code
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Parses a close payload. An empty payload is reported as
+    /// `CloseCode::Status`.
+    pub fn parse(data: &[u8]) -> Result<Self, WsError> {
+        match data.len() {
+            0 => return Ok(Self::new(CloseCode::Status, "")),
+            1 => return Err(WsError::Protocol("truncated close code")),
+            _ => {}
+        }
+
+        let code = CloseCode::from(u16::from_be_bytes([data[0], data[1]]));
+
+        if !code.is_allowed() {
+            return Err(WsError::Protocol("invalid close code"));
+        }
+
+        let reason = std::str::from_utf8(&data[2..]).map_err(|_| WsError::InvalidUtf8)?;
+
+        Ok(Self::new(code, reason))
+    }
+}
+
+fn close_payload(code: CloseCode, reason: &str) -> BytesMut {
+    let mut end = reason.len().min(123);
+
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut data = BytesMut::with_capacity(2 + end);
+    data.put_u16(code.into());
+    data.extend_from_slice(&reason.as_bytes()[..end]);
+    data
+}
+
+impl From<Message> for WsFrame {
+    fn from(msg: Message) -> Self {
+        WsFrame {
+            fin: true,
+            rsv1: false,
+            opcode: msg.opcode,
+            masked: false,
+            data: msg.data,
+        }
+    }
+}
+
+pub struct Ws {
+    fragmented: bool,
+    message: Option<(Opcode, bool, BytesMut)>,
+    inflater: Option<Inflater>,
+    text: Option<Utf8>,
+    max_frame: usize,
+    max_message: usize,
+    received: usize,
+    client: bool,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Opcode {
     CONTINUATION = 0x0,
     TEXT = 0x1,
@@ -117,123 +413,335 @@ pub enum Opcode {
     PONG = 0xA,
 }
 
-impl From<u8> for Opcode {
-    fn from(val: u8) -> Self {
+impl Opcode {
+    pub fn is_control(&self) -> bool {
+        matches!(self, Opcode::CLOSE | Opcode::PING | Opcode::PONG)
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = WsError;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
-            0x0 => Opcode::CONTINUATION,
-            0x1 => Opcode::TEXT,
-            0x2 => Opcode::BINARY,
-            0x8 => Opcode::CLOSE,
-            0x9 => Opcode::PING,
-            0xA => Opcode::PONG,
-            _ => unreachable!(),
+            0x0 => Ok(Opcode::CONTINUATION),
+            0x1 => Ok(Opcode::TEXT),
+            0x2 => Ok(Opcode::BINARY),
+            0x8 => Ok(Opcode::CLOSE),
+            0x9 => Ok(Opcode::PING),
+            0xA => Ok(Opcode::PONG),
+            _ => Err(WsError::Protocol("reserved opcode")),
+        }
+    }
+}
+
+/// A violation of RFC 6455 by the peer, or a dead peer, which fails the
+/// connection.
+#[derive(Debug, Clone)]
+pub enum WsError {
+    Protocol(&'static str),
+    InvalidUtf8,
+    /// A compressed message could not be inflated.
+    Compression,
+    /// The peer did not answer a keepalive ping in time.
+    Timeout,
+    /// A frame or message exceeded the configured size limits.
+    Size,
+}
+
+impl WsError {
+    /// The status code sent in the close frame failing the connection.
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            Self::Protocol(_) => CloseCode::Protocol,
+            Self::InvalidUtf8 | Self::Compression => CloseCode::Invalid,
+            Self::Timeout => CloseCode::Away,
+            Self::Size => CloseCode::Size,
         }
     }
 }
 
+impl fmt::Display for WsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Protocol(reason) => write!(f, "websocket protocol error: {}", reason),
+            Self::InvalidUtf8 => f.write_str("websocket text is not valid utf-8"),
+            Self::Compression => f.write_str("websocket message failed to decompress"),
+            Self::Timeout => f.write_str("websocket peer did not answer ping"),
+            Self::Size => f.write_str("websocket message too big"),
+        }
+    }
+}
+
+impl std::error::Error for WsError {}
+
+impl From<WsError> for std::io::Error {
+    fn from(err: WsError) -> Self {
+        let kind = match err {
+            WsError::Timeout => std::io::ErrorKind::TimedOut,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+
+        std::io::Error::new(kind, err)
+    }
+}
+
 impl Ws {
     pub fn new() -> Self {
         Self {
-            opcode: None,
-            length: 0,
-            data: None,
+            fragmented: false,
+            message: None,
+            inflater: None,
+            text: None,
+            max_frame: MAX_FRAME,
+            max_message: MAX_MESSAGE,
+            received: 0,
+            client: false,
         }
     }
+
+    /// A codec for the client side of a connection, which decodes the
+    /// unmasked frames sent by servers and rejects masked ones.
+    pub fn client() -> Self {
+        Self {
+            client: true,
+            ..Self::new()
+        }
+    }
+
+    /// Limits the payload of a single frame and of a whole message, after
+    /// decompression. Defaults to 16 MiB and 64 MiB.
+    pub(crate) fn set_limits(&mut self, max_frame: usize, max_message: usize) {
+        self.max_frame = max_frame;
+        self.max_message = max_message;
+    }
+
+    /// Enables permessage-deflate, allowing RSV1 on the first frame of a
+    /// message and decompressing such messages in `decode_message`.
+    pub(crate) fn set_inflater(&mut self, inflater: Inflater) {
+        self.inflater = Some(inflater);
+    }
+
+    /// Decodes frames until a complete message is available. Data frames are
+    /// buffered until their final fragment arrives, while control frames in
+    /// between are returned as soon as they are read.
+    pub fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<Message>, WsError> {
+        while let Some(frame) = self.decode(src)? {
+            let WsFrame {
+                fin,
+                rsv1,
+                opcode,
+                data,
+                ..
+            } = frame;
+
+            if opcode.is_control() {
+                if opcode == Opcode::CLOSE {
+                    CloseFrame::parse(&data)?;
+                }
+
+                return Ok(Some(Message { opcode, data }));
+            }
+
+            let (opcode, compressed, data) = match self.message.take() {
+                Some((opcode, compressed, mut buf)) => {
+                    buf.unsplit(data);
+                    (opcode, compressed, buf)
+                }
+                None => (opcode, rsv1, data),
+            };
+
+            if !fin {
+                self.message = Some((opcode, compressed, data));
+                continue;
+            }
+
+            let data = match (compressed, &mut self.inflater) {
+                (true, Some(inflater)) => inflater.decompress(&data, self.max_message)?,
+                _ => data,
+            };
+
+            // uncompressed text is validated frame by frame in `decode`
+            if compressed && opcode == Opcode::TEXT && std::str::from_utf8(&data).is_err() {
+                return Err(WsError::InvalidUtf8);
+            }
+
+            return Ok(Some(Message { opcode, data }));
+        }
+
+        Ok(None)
+    }
 }
 
 impl Decoder for Ws {
     type Item = WsFrame;
-    type Error = ();
+    type Error = WsError;
 
+    /// Decodes a single frame from the front of `src`, leaving any bytes of
+    /// following frames in place.
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() == 0 || src.len() < 6 {
+        if src.len() < 2 {
             return Ok(None);
         }
 
-        let mut curr = 0;
+        let fin = (src[0] & FIN) != 0;
+
+        let opcode = Opcode::try_from(src[0] & OPCODE)?;
+        let rsv1 = (src[0] & RSV1) != 0;
 
-        let fin_opcode = src[curr];
-        // println!("fin op {:#08b} {:#08b}", fin_opcode & 0x80, fin_opcode - 128);
+        // RSV1 is only meaningful on the first frame of a compressed message
+        let compressible =
+            self.inflater.is_some() && matches!(opcode, Opcode::TEXT | Opcode::BINARY);
 
-        let fin = (fin_opcode & FIN) == 128;
-        let opcode: Opcode = (fin_opcode & 0b0111_1111).into();
+        let allowed = if compressible { RSV1 } else { 0 };
 
-        if self.data.is_some() && opcode != Opcode::CONTINUATION {
-            return Err(());
+        if src[0] & RSV & !allowed != 0 {
+            return Err(WsError::Protocol("reserved bits set"));
         }
+        let masked = (src[1] & MASK) != 0;
 
-        match opcode {
-            Opcode::TEXT | Opcode::BINARY | Opcode::PING | Opcode::PONG | Opcode::CLOSE => {
-                self.opcode = Some(opcode.clone());
-            }
+        match (self.client, masked) {
+            (false, false) => return Err(WsError::Protocol("client frames must be masked")),
+            (true, true) => return Err(WsError::Protocol("server frames must not be masked")),
             _ => {}
         }
 
-        // println!("fin {} opcode {:?}", fin, opcode);
+        if opcode.is_control() {
+            if !fin {
+                return Err(WsError::Protocol("fragmented control frame"));
+            }
+        } else {
+            match (self.fragmented, opcode == Opcode::CONTINUATION) {
+                (false, true) => return Err(WsError::Protocol("unexpected continuation frame")),
+                (true, false) => return Err(WsError::Protocol("expected continuation frame")),
+                _ => {}
+            }
+        }
 
-        curr += 1;
+        let mut curr = 2;
 
-        let mask_length = src[curr];
-        curr += 1;
+        let length = match src[1] & !MASK {
+            126 => {
+                if src.len() < curr + 2 {
+                    return Ok(None);
+                }
 
-        let is_masked = (mask_length & MASK) == 128;
-        let length = mask_length - 128;
+                let length = Cursor::new(&src[curr..curr + 2])
+                    .read_u16::<BigEndian>()
+                    .unwrap() as u64;
+                curr += 2;
 
-        self.length = if length <= 125 {
-            length as usize
-        } else if length == 126 {
-            let mut arr = Cursor::new(&src[curr..curr + 2]);
-            curr += 2;
-            arr.read_u16::<BigEndian>().unwrap() as usize
-        } else if length == 127 {
-            let mut arr = Cursor::new(&src[curr..curr + 8]);
-            curr += 8;
-            arr.read_u64::<BigEndian>().unwrap() as usize
-        } else {
-            return Err(());
-        };
+                if length < 126 {
+                    return Err(WsError::Protocol("non-minimal payload length"));
+                }
+
+                length
+            }
+            127 => {
+                if src.len() < curr + 8 {
+                    return Ok(None);
+                }
 
-        // println!("masked {} length {}", is_masked, length);
+                let length = Cursor::new(&src[curr..curr + 8])
+                    .read_u64::<BigEndian>()
+                    .unwrap();
+                curr += 8;
 
-        let mask_key = &src[curr..curr + 4];
-        curr += 4;
+                if length >> 63 != 0 {
+                    return Err(WsError::Protocol(
+                        "payload length has most significant bit set",
+                    ));
+                }
 
-        let mut decoded = match self.data.take() {
-            Some(data) => data,
-            None => BytesMut::new(),
+                if length <= 65535 {
+                    return Err(WsError::Protocol("non-minimal payload length"));
+                }
+
+                length
+            }
+            length => length as u64,
         };
 
-        if fin && src[curr..].len() != self.length {
-            // println!("not enough bytes for complete message {}\n", src[curr..].len());
+        if opcode.is_control() && length > 125 {
+            return Err(WsError::Protocol("control frame payload too long"));
+        }
 
-            return Ok(None);
+        // checked against the limits before anything is buffered
+        let length = usize::try_from(length).map_err(|_| WsError::Size)?;
+
+        if length > self.max_frame {
+            return Err(WsError::Size);
         }
 
-        if is_masked {
-            for (i, b) in src[curr..].iter().enumerate() {
-                decoded.put_u8(b ^ mask_key[i % 4]);
+        if !opcode.is_control() {
+            let received = match opcode {
+                Opcode::CONTINUATION => self.received,
+                _ => 0,
+            };
+
+            if received + length > self.max_message {
+                return Err(WsError::Size);
+            }
+        }
+
+        let mut mask_key = [0; 4];
+
+        if masked {
+            if src.len() < curr + 4 {
+                return Ok(None);
             }
+
+            mask_key.copy_from_slice(&src[curr..curr + 4]);
+            curr += 4;
+        }
+
+        if src.len() < curr + length {
+            src.reserve((curr + length - src.len()).min(READ_AHEAD));
+            return Ok(None);
+        }
+
+        let _ = src.split_to(curr);
+        let mut data = src.split_to(length);
+
+        if masked {
+            apply_mask(&mut data, mask_key);
         }
 
-        // println!("message {:?}\n", unsafe { std::str::from_utf8_unchecked(&decoded) });
-        // println!("{:?}", src);
+        if !opcode.is_control() {
+            self.fragmented = !fin;
+            self.received = match (fin, opcode) {
+                (true, _) => 0,
+                (false, Opcode::CONTINUATION) => self.received + length,
+                (false, _) => length,
+            };
+        }
 
-        match fin {
-            true => {
-                let result = WsFrame {
-                    opcode: self.opcode.take().unwrap(),
-                    masked: is_masked,
-                    data: decoded,
-                };
-                src.clear();
-                Ok(Some(result))
+        // compressed text can only be validated once inflated
+        match opcode {
+            Opcode::TEXT if !rsv1 => {
+                let mut text = Utf8::default();
+                text.feed(&data, fin)?;
+                self.text = if fin { None } else { Some(text) };
             }
-            false => {
-                self.data = Some(decoded);
-                src.clear();
-                Ok(None)
+            Opcode::CONTINUATION => {
+                if let Some(text) = &mut self.text {
+                    text.feed(&data, fin)?;
+                }
+
+                if fin {
+                    self.text = None;
+                }
             }
+            _ => {}
         }
+
+        Ok(Some(WsFrame {
+            fin,
+            rsv1,
+            opcode,
+            masked,
+            data,
+        }))
     }
 }
 
@@ -241,59 +749,72 @@ impl Encoder<WsFrame> for Ws {
     type Error = ();
 
     fn encode(&mut self, item: WsFrame, dest: &mut BytesMut) -> Result<(), Self::Error> {
-        let mut rng = thread_rng();
-
         let mask_bit = if item.masked { MASK } else { 0 };
+        let fin_bit = if item.fin { FIN } else { 0 };
+        let rsv_bit = if item.rsv1 { RSV1 } else { 0 };
 
-        match item.opcode {
-            Opcode::BINARY | Opcode::TEXT => {
-                let fin_opcode = FIN | item.opcode as u8;
+        dest.put_u8(fin_bit | rsv_bit | item.opcode as u8);
+        mask_length(dest, mask_bit, item.data.len());
 
-                /*
-                let mask_length = if item.data.len() <= 125 {
-                    mask_bit | item.data.len() as u8
-                } else {
-                    126
-                };
-                */
+        let mut data = item.data;
 
-                dest.put_u8(fin_opcode);
-                // dest.put_u8(mask_length);
-
-                mask_length(dest, mask_bit, item.data.len());
+        if item.masked {
+            let mut mask = [0; 4];
+            thread_rng().fill_bytes(&mut mask);
+            dest.extend_from_slice(&mask);
+            apply_mask(&mut data, mask);
+        }
 
-                let mut mask = [0; 4];
+        dest.extend_from_slice(&data);
 
-                if item.masked {
-                    rng.fill_bytes(&mut mask);
-                    dest.extend_from_slice(&mask);
-                }
+        Ok(())
+    }
+}
 
-                let mut temp = item.data.clone();
+/// Validates the UTF-8 of a text message fragment by fragment, so invalid
+/// text is rejected without waiting for the final frame. A character split
+/// across fragments is carried over to the next one.
+#[derive(Default)]
+struct Utf8 {
+    partial: Vec<u8>,
+}
 
-                if item.masked {
-                    for i in 0..temp.len() {
-                        temp[i] = temp[i] ^ mask[i % 4];
-                    }
-                }
+impl Utf8 {
+    fn feed(&mut self, mut data: &[u8], fin: bool) -> Result<(), WsError> {
+        // complete the character left over from the previous fragment
+        while !self.partial.is_empty() && !data.is_empty() {
+            self.partial.push(data[0]);
+            data = &data[1..];
+
+            match std::str::from_utf8(&self.partial) {
+                Ok(_) => self.partial.clear(),
+                Err(e) if e.error_len().is_some() => return Err(WsError::InvalidUtf8),
+                Err(_) => {}
+            }
+        }
 
-                dest.extend_from_slice(&temp);
+        if let Err(e) = std::str::from_utf8(data) {
+            if e.error_len().is_some() {
+                return Err(WsError::InvalidUtf8);
             }
-            Opcode::CLOSE | Opcode::PING | Opcode::PONG => {
-                let fin_opcode = FIN | item.opcode as u8;
 
-                let mask_length = 0;
+            self.partial.extend_from_slice(&data[e.valid_up_to()..]);
+        }
 
-                dest.put_u8(fin_opcode);
-                dest.put_u8(mask_length);
-            }
-            _ => {}
+        if fin && !self.partial.is_empty() {
+            return Err(WsError::InvalidUtf8);
         }
 
         Ok(())
     }
 }
 
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
 fn mask_length(dest: &mut BytesMut, mask_bit: u8, length: usize) {
     match length {
         x if x <= 125 => {
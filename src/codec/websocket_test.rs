@@ -0,0 +1,290 @@
+use crate::codec::deflate::Deflate;
+use crate::codec::websocket::{CloseCode, CloseFrame, Message, Opcode, Ws, WsFrame};
+use crate::codec::{Decoder, Encoder};
+use bytes::BytesMut;
+
+fn client_frame(frame: WsFrame) -> BytesMut {
+    let mut buf = BytesMut::new();
+    Ws::new().encode(frame, &mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn ws_decode_streaming() {
+    let mut buf = client_frame(WsFrame::builder().masked().text("hello"));
+    buf.extend_from_slice(&client_frame(
+        WsFrame::builder().masked().binary(vec![7; 300]),
+    ));
+
+    let mut ws = Ws::new();
+    let all = buf.split();
+
+    // feed one byte at a time, both frames must come out intact
+    let mut frames = Vec::new();
+    for b in all.iter() {
+        buf.extend_from_slice(&[*b]);
+        while let Some(frame) = ws.decode(&mut buf).unwrap() {
+            frames.push(frame);
+        }
+    }
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].opcode(), &Opcode::TEXT);
+    assert_eq!(frames[0].data(), b"hello");
+    assert_eq!(frames[1].opcode(), &Opcode::BINARY);
+    assert_eq!(frames[1].len(), 300);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn ws_decode_rejects_invalid_frames() {
+    let cases: [&[u8]; 5] = [
+        // reserved opcode 0x3
+        &[0x83, 0x80, 0, 0, 0, 0],
+        // rsv2 set
+        &[0xA1, 0x80, 0, 0, 0, 0],
+        // unmasked client frame
+        &[0x81, 0x00],
+        // fragmented ping
+        &[0x09, 0x80, 0, 0, 0, 0],
+        // continuation without a message
+        &[0x80, 0x80, 0, 0, 0, 0],
+    ];
+
+    for case in cases {
+        let mut buf = BytesMut::from(case);
+        let err = Ws::new().decode(&mut buf).unwrap_err();
+        assert_eq!(err.close_code(), CloseCode::Protocol);
+    }
+}
+
+#[test]
+fn ws_decode_waits_for_extended_length() {
+    let mut ws = Ws::new();
+    let mut buf = BytesMut::from(&[0x82, 0xFF, 0, 0][..]);
+    assert!(ws.decode(&mut buf).unwrap().is_none());
+
+    // control frames may not use extended lengths
+    let mut buf = BytesMut::from(&[0x89, 0xFE, 0, 126, 0, 0, 0, 0][..]);
+    assert!(Ws::new().decode(&mut buf).is_err());
+}
+
+#[test]
+fn ws_decode_does_not_reserve_announced_length() {
+    // a masked 8 MiB binary frame, of which only the header has arrived
+    let mut buf = BytesMut::from(&[0x82, 0xFF, 0, 0, 0, 0, 0, 0x80, 0, 0, 1, 2, 3, 4][..]);
+    assert!(Ws::new().decode(&mut buf).unwrap().is_none());
+    assert!(buf.capacity() < 64 << 10);
+}
+
+#[test]
+fn ws_reassembles_fragments_around_control_frames() {
+    let mut buf = client_frame(WsFrame::builder().masked().fragment().text("hel"));
+    buf.extend_from_slice(&client_frame(WsFrame::builder().masked().ping()));
+    buf.extend_from_slice(&client_frame(
+        WsFrame::builder().masked().fragment().continuation("lo "),
+    ));
+    buf.extend_from_slice(&client_frame(
+        WsFrame::builder().masked().continuation("world"),
+    ));
+
+    let mut ws = Ws::new();
+
+    let ping = ws.decode_message(&mut buf).unwrap().unwrap();
+    assert_eq!(ping.opcode(), &Opcode::PING);
+
+    let msg = ws.decode_message(&mut buf).unwrap().unwrap();
+    assert_eq!(msg.opcode(), &Opcode::TEXT);
+    assert_eq!(msg.data(), b"hello world");
+
+    assert!(ws.decode_message(&mut buf).unwrap().is_none());
+}
+
+#[test]
+fn ws_close_payloads() {
+    let msg = Message::close(CloseCode::Other(4001), "bye");
+    let close = msg.close_frame().unwrap();
+    assert_eq!(close.code(), CloseCode::Other(4001));
+    assert_eq!(close.reason(), "bye");
+
+    assert_eq!(CloseFrame::parse(&[]).unwrap().code(), CloseCode::Status);
+    assert!(CloseFrame::parse(&[0x03]).is_err());
+    assert!(CloseFrame::parse(&1005u16.to_be_bytes()).is_err());
+    assert!(CloseFrame::parse(&999u16.to_be_bytes()).is_err());
+    assert_eq!(
+        CloseFrame::parse(&[0x03, 0xE8, 0xFF])
+            .unwrap_err()
+            .close_code(),
+        CloseCode::Invalid
+    );
+
+    // reasons are cut to fit a control frame on a char boundary
+    let long = "é".repeat(100);
+    let msg = Message::close(CloseCode::Normal, &long);
+    assert!(msg.len() <= 125);
+    assert!(msg.close_frame().is_some());
+}
+
+#[test]
+fn ws_deflate_negotiation() {
+    let deflate = Deflate::new();
+
+    let (_, response) = deflate
+        .negotiate("permessage-deflate; client_max_window_bits")
+        .unwrap();
+    assert_eq!(response, "permessage-deflate");
+
+    // the first offer limits our window, the fallback is accepted
+    let (_, response) = deflate
+        .negotiate("permessage-deflate; server_max_window_bits=10, permessage-deflate; server_no_context_takeover")
+        .unwrap();
+    assert_eq!(response, "permessage-deflate; server_no_context_takeover");
+
+    // a full window is what we use anyway, but it has to be echoed
+    let (_, response) = deflate
+        .negotiate("permessage-deflate; server_max_window_bits=15")
+        .unwrap();
+    assert_eq!(response, "permessage-deflate; server_max_window_bits=15");
+
+    assert!(deflate.negotiate("x-webkit-deflate-frame").is_none());
+    assert!(deflate.negotiate("permessage-deflate; foo").is_none());
+    assert!(deflate
+        .negotiate("permessage-deflate; client_no_context_takeover; client_no_context_takeover")
+        .is_none());
+}
+
+#[test]
+fn ws_deflate_messages() {
+    let (params, _) = Deflate::new().negotiate("permessage-deflate").unwrap();
+
+    // "Hello" from RFC 7692 section 7.2.3.1
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&[0xc1, 0x87, 0, 0, 0, 0]);
+    buf.extend_from_slice(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+
+    let mut ws = Ws::new();
+    ws.set_inflater(params.inflater());
+    let msg = ws.decode_message(&mut buf).unwrap().unwrap();
+    assert_eq!(msg.data(), b"Hello");
+
+    // a compressed message split over fragments, sharing the window
+    let mut deflater = params.deflater();
+    let text = "compress me ".repeat(50);
+    for _ in 0..2 {
+        let first = WsFrame::builder()
+            .masked()
+            .fragment()
+            .text(text[..300].to_string());
+        let last = WsFrame::builder()
+            .masked()
+            .continuation(text[300..].to_string());
+        let mut buf = client_frame(first.compress(&mut deflater));
+        buf.extend_from_slice(&client_frame(last.compress(&mut deflater)));
+
+        let msg = ws.decode_message(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.opcode(), &Opcode::TEXT);
+        assert_eq!(msg.data(), text.as_bytes());
+    }
+
+    // RSV1 is rejected unless the extension was negotiated
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&[0xc1, 0x80, 0, 0, 0, 0]);
+    assert!(Ws::new().decode(&mut buf).is_err());
+}
+
+#[test]
+fn ws_validates_text_across_fragments() {
+    // "é" is split between the two fragments
+    let text = "caf\u{e9}".as_bytes();
+    let mut buf = client_frame(
+        WsFrame::builder()
+            .masked()
+            .fragment()
+            .text(text[..4].to_vec()),
+    );
+    buf.extend_from_slice(&client_frame(
+        WsFrame::builder().masked().continuation(text[4..].to_vec()),
+    ));
+
+    let mut ws = Ws::new();
+    let msg = ws.decode_message(&mut buf).unwrap().unwrap();
+    assert_eq!(msg.as_str(), Some("caf\u{e9}"));
+    assert_eq!(msg.into_string().unwrap(), "caf\u{e9}");
+
+    // invalid bytes fail the first fragment without waiting for the rest
+    let mut buf = client_frame(
+        WsFrame::builder()
+            .masked()
+            .fragment()
+            .text(vec![b'a', 0xff]),
+    );
+    let err = Ws::new().decode_message(&mut buf).unwrap_err();
+    assert_eq!(err.close_code(), CloseCode::Invalid);
+
+    // a character left incomplete by the final fragment
+    let mut buf = client_frame(WsFrame::builder().masked().text(text[..4].to_vec()));
+    assert!(Ws::new().decode_message(&mut buf).is_err());
+
+    // binary data is never validated
+    let mut buf = client_frame(WsFrame::builder().masked().binary(vec![0xff]));
+    let msg = Ws::new().decode_message(&mut buf).unwrap().unwrap();
+    assert_eq!(msg.as_str(), None);
+}
+
+#[test]
+fn ws_enforces_size_limits() {
+    let mut ws = Ws::new();
+    ws.set_limits(1024, 4096);
+
+    // rejected from the header alone, nothing of the payload is buffered
+    let mut buf = BytesMut::from(&[0x82, 0xff, 0, 0, 0, 1, 0, 0, 0, 0][..]);
+    let err = ws.decode(&mut buf).unwrap_err();
+    assert_eq!(err.close_code(), CloseCode::Size);
+    assert!(buf.capacity() < 1024);
+
+    // fragments that fit on their own but not together
+    let mut buf = BytesMut::new();
+    for i in 0..5 {
+        let frame = match i {
+            0 => WsFrame::builder().masked().fragment().binary(vec![0; 1000]),
+            _ => WsFrame::builder()
+                .masked()
+                .fragment()
+                .continuation(vec![0; 1000]),
+        };
+        buf.extend_from_slice(&client_frame(frame));
+    }
+
+    let mut ws = Ws::new();
+    ws.set_limits(1024, 4096);
+    let err = ws.decode_message(&mut buf).unwrap_err();
+    assert_eq!(err.close_code(), CloseCode::Size);
+
+    // a small compressed frame inflating past the message limit
+    let (params, _) = Deflate::new().negotiate("permessage-deflate").unwrap();
+    let frame = WsFrame::builder()
+        .masked()
+        .binary(vec![0; 100_000])
+        .compress(&mut params.deflater());
+
+    let mut ws = Ws::new();
+    ws.set_inflater(params.inflater());
+    ws.set_limits(1024, 4096);
+    let err = ws.decode_message(&mut client_frame(frame)).unwrap_err();
+    assert_eq!(err.close_code(), CloseCode::Size);
+
+    // no limit at all
+    let frame = WsFrame::builder()
+        .masked()
+        .binary(vec![0; 100_000])
+        .compress(&mut params.deflater());
+
+    let mut ws = Ws::new();
+    ws.set_inflater(params.inflater());
+    ws.set_limits(usize::MAX, usize::MAX);
+    let msg = ws
+        .decode_message(&mut client_frame(frame))
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.len(), 100_000);
+}
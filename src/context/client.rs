@@ -0,0 +1,326 @@
+use http::header::{HeaderMap, FORWARDED, HOST};
+use http::uri::Scheme;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// The originating client of a request, as resolved through any trusted
+/// proxies in front of the server.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    ip: IpAddr,
+    scheme: Scheme,
+    host: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    pub fn scheme(&self) -> &Scheme {
+        &self.scheme
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+}
+
+#[derive(Debug)]
+pub struct CidrError;
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid cidr block")
+    }
+}
+
+impl std::error::Error for CidrError {}
+
+/// An IPv4 or IPv6 address block such as `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr = canonical(addr.parse().map_err(|_| CidrError)?);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| CidrError)?,
+            None => max,
+        };
+
+        if prefix > max {
+            return Err(CidrError);
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// The set of proxies whose `Forwarded` and `X-Forwarded-*` headers are
+/// believed. Headers arriving from any other peer are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    cidrs: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    pub fn new() -> Self {
+        Self { cidrs: Vec::new() }
+    }
+
+    /// Trusts every address in `cidr`.
+    ///
+    /// Panics if `cidr` is not an address or address block, use
+    /// `trust_cidr` to handle the error instead.
+    pub fn trust(self, cidr: &str) -> Self {
+        match cidr.parse() {
+            Ok(cidr) => self.trust_cidr(cidr),
+            Err(_) => panic!("invalid trusted proxy {:?}", cidr),
+        }
+    }
+
+    pub fn trust_cidr(mut self, cidr: Cidr) -> Self {
+        self.cidrs.push(cidr);
+        self
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Resolves the client of a request received from `peer`.
+    ///
+    /// The forwarding chain is walked from the nearest hop outwards and the
+    /// first address that is not a trusted proxy is taken as the client.
+    /// `Forwarded` takes precedence over the `X-Forwarded-*` headers.
+    pub fn resolve(&self, peer: SocketAddr, headers: &HeaderMap) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: canonical(peer.ip()),
+            scheme: Scheme::HTTP,
+            host: header_str(headers, HOST).map(String::from),
+        };
+
+        if !self.is_trusted(client.ip) {
+            return client;
+        }
+
+        let hops = match forwarded(headers) {
+            Some(hops) => hops,
+            None => x_forwarded(headers),
+        };
+
+        for hop in hops.iter().rev() {
+            let ip = match hop.ip {
+                Some(ip) => canonical(ip),
+                None => break,
+            };
+
+            client.ip = ip;
+
+            if let Some(scheme) = hop.proto.as_deref().and_then(|s| s.parse().ok()) {
+                client.scheme = scheme;
+            }
+
+            if let Some(host) = &hop.host {
+                client.host = Some(host.clone());
+            }
+
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+#[derive(Debug, Default)]
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn forwarded(headers: &HeaderMap) -> Option<Vec<Hop>> {
+    let mut hops = Vec::new();
+
+    for value in headers.get_all(FORWARDED) {
+        let value = value.to_str().ok()?;
+
+        for element in split_quoted(value, ',') {
+            let mut hop = Hop::default();
+
+            for pair in split_quoted(element, ';') {
+                let (k, v) = match pair.split_once('=') {
+                    Some((k, v)) => (k.trim(), unquote(v.trim())),
+                    None => continue,
+                };
+
+                if k.eq_ignore_ascii_case("for") {
+                    hop.ip = parse_node(v);
+                } else if k.eq_ignore_ascii_case("proto") {
+                    hop.proto = Some(v.to_ascii_lowercase());
+                } else if k.eq_ignore_ascii_case("host") {
+                    hop.host = Some(v.to_string());
+                }
+            }
+
+            hops.push(hop);
+        }
+    }
+
+    if hops.is_empty() {
+        None
+    } else {
+        Some(hops)
+    }
+}
+
+fn x_forwarded(headers: &HeaderMap) -> Vec<Hop> {
+    let list = |name| -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+
+    let mut hops: Vec<Hop> = list(X_FORWARDED_FOR)
+        .iter()
+        .map(|v| Hop {
+            ip: parse_node(v),
+            ..Hop::default()
+        })
+        .collect();
+
+    // Proxies that only append to `X-Forwarded-For` usually overwrite the
+    // proto and host headers, so these are matched up by position only when
+    // the lists line up and are otherwise attributed to the nearest hop.
+    for (name, proto) in [(X_FORWARDED_PROTO, true), (X_FORWARDED_HOST, false)] {
+        let mut values = list(name);
+
+        if values.len() != hops.len() {
+            values = values.pop().into_iter().collect();
+        }
+
+        let offset = hops.len() - values.len().min(hops.len());
+
+        for (hop, value) in hops[offset..].iter_mut().zip(values) {
+            if proto {
+                hop.proto = Some(value.to_ascii_lowercase());
+            } else {
+                hop.host = Some(value);
+            }
+        }
+    }
+
+    hops
+}
+
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let end = rest.find(']')?;
+        return rest[..end].parse().ok();
+    }
+
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+
+    match node.rsplit_once(':') {
+        Some((ip, _port)) => ip.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4),
+        None => None,
+    }
+}
+
+fn split_quoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(s[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+fn header_str<K: http::header::AsHeaderName>(headers: &HeaderMap, name: K) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        },
+        ip => ip,
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let bytes = (prefix / 8) as usize;
+    let bits = prefix % 8;
+
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+
+    if bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - bits);
+    a[bytes] & mask == b[bytes] & mask
+}
@@ -0,0 +1,73 @@
+use crate::context::TrustedProxies;
+use http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (k, v) in pairs {
+        headers.append(*k, v.parse().unwrap());
+    }
+    headers
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn untrusted_peer_ignores_headers() {
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8");
+    let peer: SocketAddr = "203.0.113.9:5000".parse().unwrap();
+    let headers = headers(&[
+        ("x-forwarded-for", "1.2.3.4"),
+        ("x-forwarded-proto", "https"),
+        ("host", "example.com"),
+    ]);
+
+    let client = proxies.resolve(peer, &headers);
+    assert_eq!(client.ip(), ip("203.0.113.9"));
+    assert_eq!(client.scheme().as_str(), "http");
+    assert_eq!(client.host(), Some("example.com"));
+}
+
+#[test]
+fn x_forwarded_skips_trusted_hops() {
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8");
+    let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+    let headers = headers(&[
+        ("x-forwarded-for", "6.6.6.6, 198.51.100.7, 10.1.2.3"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "api.example.com"),
+    ]);
+
+    let client = proxies.resolve(peer, &headers);
+    assert_eq!(client.ip(), ip("198.51.100.7"));
+    assert_eq!(client.scheme().as_str(), "https");
+    assert_eq!(client.host(), Some("api.example.com"));
+}
+
+#[test]
+fn forwarded_takes_precedence() {
+    let proxies = TrustedProxies::new().trust("::1").trust("192.0.2.0/24");
+    let peer: SocketAddr = "[::1]:5000".parse().unwrap();
+    let headers = headers(&[
+        (
+            "forwarded",
+            "for=\"[2001:db8:cafe::17]:4711\";proto=https;host=a.example, for=192.0.2.43",
+        ),
+        ("x-forwarded-for", "1.2.3.4"),
+    ]);
+
+    let client = proxies.resolve(peer, &headers);
+    assert_eq!(client.ip(), ip("2001:db8:cafe::17"));
+    assert_eq!(client.scheme().as_str(), "https");
+    assert_eq!(client.host(), Some("a.example"));
+}
+
+#[test]
+fn ipv4_mapped_peer_matches_v4_cidr() {
+    let proxies = TrustedProxies::new().trust("127.0.0.1");
+    assert!(proxies.is_trusted(ip("::ffff:127.0.0.1")));
+    assert!(!proxies.is_trusted(ip("127.0.0.2")));
+    assert!("10.0.0.0/33".parse::<crate::context::Cidr>().is_err());
+}
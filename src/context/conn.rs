@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Allocates an identifier that no connection or hub client shares.
+pub(crate) fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Metadata about the connection a request arrived on.
+///
+/// Inserted into the extensions of every request before it reaches a route,
+/// and into the websocket `Receiver` extensions after an upgrade.
+#[derive(Debug, Clone)]
+pub struct ConnInfo {
+    id: u64,
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    requests: u64,
+}
+
+impl ConnInfo {
+    pub(crate) fn new(peer_addr: SocketAddr, local_addr: SocketAddr) -> Self {
+        Self {
+            id: next_id(),
+            peer_addr,
+            local_addr,
+            requests: 0,
+        }
+    }
+
+    /// Process-wide unique identifier of the connection.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Number of requests read on this connection so far, including the
+    /// current one.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    pub(crate) fn next_request(&mut self) {
+        self.requests += 1;
+    }
+}
@@ -1,15 +1,27 @@
 use crate::codec::{
+    deflate::{Deflater, Inflater},
     http::Http,
-    websocket::{Ws, WsFrame},
+    websocket::{CloseCode, CloseFrame, Message, Opcode, Ws, WsError, WsFrame},
     Decoder, Encoder,
 };
+use crate::context::queue::{Overflow, QueueStats, SendQueue};
 use crate::context::Body;
 use bytes::BytesMut;
-use http::Response;
+use futures_core::{ready, Stream};
+use futures_sink::Sink;
+use http::{Extensions, Response};
+use std::future::{self, Future};
 use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{self, Poll};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{ReadHalf, WriteHalf};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time::{self, Instant, Sleep};
 
 pub struct Context<'a, Codec> {
     stream: &'a mut TcpStream,
@@ -28,10 +40,22 @@ impl<'a, Codec> Context<'a, Codec> {
 }
 
 impl<'a> Context<'a, Ws> {
-    pub fn split(&'a mut self) -> (Sender<'a, Ws>, Receiver<'a, Ws>) {
-        let (reader, writer) = self.stream.split();
+    /// Splits an upgraded connection into owned halves, which are `Send` and
+    /// `'static` so they can be moved into spawned tasks.
+    pub fn split(stream: TcpStream) -> (Sender<Ws>, Receiver<Ws>) {
+        let (reader, writer) = stream.into_split();
         let tx = Sender::new(writer);
-        let rx = Receiver::new(reader);
+        let rx = Receiver::new(reader, &tx);
+        (tx, rx)
+    }
+
+    /// Like `split`, for the client side of a connection. Outgoing frames
+    /// are masked and incoming ones must not be.
+    pub(crate) fn split_client(stream: TcpStream) -> (Sender<Ws>, Receiver<Ws>) {
+        let (reader, writer) = stream.into_split();
+        let tx = Sender::with_mask(writer, true);
+        let mut rx = Receiver::new(reader, &tx);
+        rx.codec = Ws::client();
         (tx, rx)
     }
 
@@ -39,22 +63,16 @@ impl<'a> Context<'a, Ws> {
 
     pub async fn next(&mut self) -> std::io::Result<WsFrame> {
         let mut ws = Ws::new();
-
         let bytes = &mut self.buffers[0];
-        self.stream.read_buf(bytes).await?;
-
-        let mut res = ws.decode(bytes);
-
-        while let Ok(None) = res {
-            self.stream.read_buf(bytes).await?;
-            res = ws.decode(bytes);
-        }
 
-        bytes.clear();
+        loop {
+            if let Some(frame) = ws.decode(bytes)? {
+                return Ok(frame);
+            }
 
-        match res.unwrap() {
-            Some(msg) => return Ok(msg),
-            _ => unreachable!(),
+            if self.stream.read_buf(bytes).await? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
         }
     }
 
@@ -77,63 +95,672 @@ impl<'a> Context<'a, Http<Body>> {
     }
 }
 
-pub struct Sender<'a, Codec> {
-    writer: WriteHalf<'a>,
+/// The write half of a websocket, shared by the clones of a `Sender` and
+/// the `Receiver` so the latter can answer pings and send keepalives.
+struct Writer {
+    inner: Mutex<WriterInner>,
+    /// Held while a data message is written, across all of its fragments,
+    /// so control frames can still go out between them.
+    message: Mutex<()>,
+    closed: AtomicBool,
+    /// Whether frames are masked, as clients must.
+    mask: bool,
+    control: std::sync::Mutex<Control>,
+}
+
+/// Control frames of the `Receiver` waiting to be written, by at most one
+/// task at a time. Only the latest pong is kept, as RFC 6455 5.5.3 allows,
+/// so a peer flooding pings without reading the pongs cannot pile them up.
+#[derive(Default)]
+struct Control {
+    pong: Option<WsFrame>,
+    ping: Option<WsFrame>,
+    writing: bool,
+}
+
+struct WriterInner {
+    writer: OwnedWriteHalf,
     buf: BytesMut,
+    deflater: Option<Deflater>,
+}
+
+impl Writer {
+    /// Writes a frame, compressing its payload when `compress` is set and
+    /// permessage-deflate was negotiated.
+    async fn write(&self, msg: WsFrame, compress: bool) -> std::io::Result<()> {
+        let _message = match msg.opcode().is_control() {
+            true => None,
+            false => Some(self.message.lock().await),
+        };
+
+        let mut inner = self.inner.lock().await;
+        self.write_locked(&mut inner, msg, compress).await
+    }
+
+    /// Writes a control frame of the `Receiver` from a task of its own, so
+    /// reads never wait on the writer.
+    fn write_control(self: &Arc<Self>, frame: WsFrame) {
+        let mut control = self.control.lock().unwrap();
+
+        match frame.opcode() {
+            Opcode::PONG => control.pong = Some(frame),
+            _ => control.ping = Some(frame),
+        }
+
+        if control.writing {
+            return;
+        }
+
+        control.writing = true;
+        let writer = self.clone();
+        tokio::spawn(async move { writer.drain_control().await });
+    }
+
+    async fn drain_control(&self) {
+        loop {
+            let frame = {
+                let mut control = self.control.lock().unwrap();
+
+                match control.pong.take().or_else(|| control.ping.take()) {
+                    Some(frame) => frame,
+                    None => {
+                        control.writing = false;
+                        return;
+                    }
+                }
+            };
+
+            if let Err(e) = self.write(frame, false).await {
+                log::debug!("websocket control frame not sent {}", e);
+                *self.control.lock().unwrap() = Control::default();
+                return;
+            }
+        }
+    }
+
+    async fn write_locked(
+        &self,
+        inner: &mut WriterInner,
+        mut msg: WsFrame,
+        compress: bool,
+    ) -> std::io::Result<()> {
+        msg.masked |= self.mask;
+
+        if self.closed.load(Ordering::Acquire) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "websocket close frame already sent",
+            ));
+        }
+
+        if msg.opcode() == &Opcode::CLOSE {
+            self.closed.store(true, Ordering::Release);
+        }
+
+        let WriterInner {
+            writer,
+            buf,
+            deflater,
+        } = inner;
+
+        let msg = match deflater {
+            Some(deflater) if compress => msg.compress(deflater),
+            _ => msg,
+        };
+
+        let mut ws = Ws::new();
+        ws.encode(msg, buf).unwrap();
+
+        let res = writer.write_all(buf).await;
+        buf.clear();
+        res
+    }
+}
+
+/// The sending half of a websocket. It owns its half of the connection, so
+/// it can be cloned and moved into other tasks, letting many producers
+/// push messages to one client.
+pub struct Sender<Codec> {
+    writer: Arc<Writer>,
+    queue: Option<Arc<SendQueue>>,
+    pending: Option<WriteFuture>,
     _marker: PhantomData<Codec>,
 }
 
-impl<'a, Codec> Sender<'a, Codec> {
-    pub fn new(writer: WriteHalf<'a>) -> Self {
+type WriteFuture = Pin<Box<dyn Future<Output = std::io::Result<()>> + Send + Sync>>;
+
+// `Codec` is only a marker, nothing is pinned
+impl<Codec> Unpin for Sender<Codec> {}
+
+impl<Codec> Clone for Sender<Codec> {
+    fn clone(&self) -> Self {
         Self {
+            writer: self.writer.clone(),
+            queue: self.queue.clone(),
+            pending: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Codec> Sender<Codec> {
+    pub fn new(writer: OwnedWriteHalf) -> Self {
+        Self::with_mask(writer, false)
+    }
+
+    fn with_mask(writer: OwnedWriteHalf, mask: bool) -> Self {
+        let inner = WriterInner {
             writer,
             buf: BytesMut::new(),
+            deflater: None,
+        };
+
+        let writer = Writer {
+            inner: Mutex::new(inner),
+            message: Mutex::new(()),
+            closed: AtomicBool::new(false),
+            mask,
+            control: std::sync::Mutex::default(),
+        };
+
+        Self {
+            writer: Arc::new(writer),
+            queue: None,
+            pending: None,
             _marker: PhantomData,
         }
     }
 
+    /// Writes a frame. Fails once a close frame has been sent, as nothing
+    /// may follow it.
     pub async fn write(&mut self, msg: WsFrame) -> std::io::Result<()> {
-        let mut ws = Ws::new();
-        ws.encode(msg, &mut self.buf).unwrap();
+        self.dispatch(msg, false).await
+    }
 
-        let res = self.writer.write_all(&self.buf).await;
-        self.buf.clear();
-        res
+    /// Sends a message, compressing text and binary messages when
+    /// permessage-deflate was negotiated.
+    ///
+    /// With a send queue, see `WsConfig::send_queue`, this only waits for
+    /// the message to be queued and a failed write surfaces on a later send.
+    pub async fn send(&mut self, msg: Message) -> std::io::Result<()> {
+        let compress = !msg.opcode().is_control();
+        self.dispatch(msg.into(), compress).await
+    }
+
+    /// Waits until every queued message has been written. Returns at once
+    /// without a send queue.
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        match &self.queue {
+            Some(queue) => queue.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Depth and counters of the send queue, if the route has one.
+    pub fn queue_stats(&self) -> Option<QueueStats> {
+        self.queue.as_ref().map(|queue| queue.stats())
+    }
+
+    /// Starts the close handshake. The connection is shut down once the
+    /// handler returns and the peer has answered, or the close timeout of
+    /// the route has passed.
+    pub async fn close(&mut self, code: CloseCode, reason: &str) -> std::io::Result<()> {
+        self.write(WsFrame::builder().close_with(code, reason))
+            .await
+    }
+
+    /// Whether a close frame has been sent, or queued, by this or any other
+    /// clone.
+    pub fn is_closed(&self) -> bool {
+        match &self.queue {
+            Some(queue) if queue.is_closing() => true,
+            _ => self.writer.closed.load(Ordering::Acquire),
+        }
+    }
+
+    /// Routes the messages of this sender and its clones through a bounded
+    /// queue, written by a task spawned on the current runtime.
+    pub(crate) fn set_queue(&mut self, capacity: usize, overflow: Overflow) {
+        let queue = Arc::new(SendQueue::new(capacity, overflow));
+        let task = tokio::spawn(drain(self.writer.clone(), queue.clone()));
+        queue.set_task(task);
+        self.queue = Some(queue);
+    }
+
+    /// Stops writing queued messages, once the connection is done with.
+    pub(crate) fn stop_queue(&self) {
+        if let Some(queue) = &self.queue {
+            queue.stop();
+        }
+    }
+
+    pub(crate) fn set_deflater(&mut self, deflater: Deflater) {
+        match self.writer.inner.try_lock() {
+            Ok(mut inner) => inner.deflater = Some(deflater),
+            Err(_) => unreachable!("deflate configured while writing"),
+        }
+    }
+
+    /// Starts sending a text or binary message as a sequence of fragments,
+    /// so large payloads do not have to be buffered in full. Other clones of
+    /// the sender wait to send messages until this one is finished or
+    /// dropped, while control frames, such as the pongs of the `Receiver`,
+    /// still go out between fragments.
+    ///
+    /// Fragments bypass the send queue, call `flush` first to keep them in
+    /// order with queued messages.
+    ///
+    /// Panics if `opcode` is not `TEXT` or `BINARY`.
+    pub fn fragments(&mut self, opcode: Opcode) -> Fragments<'_, Codec> {
+        assert!(matches!(opcode, Opcode::TEXT | Opcode::BINARY));
+
+        Fragments {
+            writer: &self.writer,
+            message: None,
+            opcode,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Codec> Sender<Codec> {
+    fn dispatch(&self, msg: WsFrame, compress: bool) -> WriteFuture {
+        let writer = self.writer.clone();
+        let queue = self.queue.clone();
+
+        Box::pin(async move {
+            match queue {
+                Some(queue) => queue.push(msg, compress).await,
+                None => writer.write(msg, compress).await,
+            }
+        })
+    }
+
+    fn start_write(&mut self, msg: WsFrame, compress: bool) {
+        self.pending = Some(self.dispatch(msg, compress));
+    }
+
+    fn poll_pending(&mut self, cx: &mut task::Context<'_>) -> Poll<std::io::Result<()>> {
+        if let Some(pending) = &mut self.pending {
+            let res = ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+            res?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Messages are written one at a time, each as soon as it is started.
+/// Closing the sink sends a normal close frame unless one was sent already.
+impl<Codec> Sink<Message> for Sender<Codec> {
+    type Error = std::io::Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: Message) -> Result<(), Self::Error> {
+        let compress = !msg.opcode().is_control();
+        self.get_mut().start_write(msg.into(), compress);
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+
+        if !this.is_closed() {
+            this.start_write(WsFrame::builder().close_with(CloseCode::Normal, ""), false);
+            ready!(this.poll_pending(cx))?;
+        }
+
+        Poll::Ready(Ok(()))
     }
 }
 
-pub struct Receiver<'a, Codec> {
-    reader: ReadHalf<'a>,
+/// Writes the frames of a send queue until a close frame has been written or
+/// a write fails.
+async fn drain(writer: Arc<Writer>, queue: Arc<SendQueue>) {
+    loop {
+        let (frame, compress) = queue.pop().await;
+        let close = frame.opcode() == &Opcode::CLOSE;
+        let res = writer.write(frame, compress).await;
+        queue.written(&res);
+
+        if let Err(e) = res {
+            log::debug!("websocket send queue failed {}", e);
+            return;
+        }
+
+        if close {
+            return;
+        }
+    }
+}
+
+pub struct Fragments<'s, Codec> {
+    writer: &'s Writer,
+    message: Option<MutexGuard<'s, ()>>,
+    opcode: Opcode,
+    _marker: PhantomData<Codec>,
+}
+
+impl<'s, Codec> Fragments<'s, Codec> {
+    /// Sends the next non-final fragment.
+    pub async fn write(&mut self, fragment: impl Into<Body>) -> std::io::Result<()> {
+        let frame = WsFrame::builder()
+            .fragment()
+            .body(self.opcode, Some(fragment.into()));
+
+        self.write_frame(frame).await?;
+        self.opcode = Opcode::CONTINUATION;
+        Ok(())
+    }
+
+    /// Sends the final fragment, completing the message.
+    pub async fn finish(mut self, fragment: impl Into<Body>) -> std::io::Result<()> {
+        let frame = WsFrame::builder().body(self.opcode, Some(fragment.into()));
+        self.write_frame(frame).await
+    }
+
+    /// Sends a control frame between two fragments of the message.
+    pub async fn control(&mut self, msg: Message) -> std::io::Result<()> {
+        if !msg.opcode().is_control() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "data message sent between fragments",
+            ));
+        }
+
+        self.writer.write(msg.into(), false).await
+    }
+
+    // the message stays locked from the first fragment on, the writer only
+    // while a frame is written
+    async fn write_frame(&mut self, frame: WsFrame) -> std::io::Result<()> {
+        if self.message.is_none() {
+            self.message = Some(self.writer.message.lock().await);
+        }
+
+        let mut inner = self.writer.inner.lock().await;
+        self.writer.write_locked(&mut inner, frame, true).await
+    }
+}
+
+/// The receiving half of a websocket, owning its half of the connection.
+pub struct Receiver<Codec> {
+    reader: OwnedReadHalf,
+    writer: Arc<Writer>,
     buf: BytesMut,
+    codec: Ws,
+    keepalive: Option<Keepalive>,
+    error: Option<WsError>,
+    close: Option<CloseFrame>,
+    protocol: Option<String>,
+    extensions: Extensions,
+    terminated: bool,
     _marker: PhantomData<Codec>,
 }
 
-impl<'a, Codec> Receiver<'a, Codec> {
-    pub fn new(reader: ReadHalf<'a>) -> Self {
+impl<Codec> Unpin for Receiver<Codec> {}
+
+struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    ping_sent: bool,
+}
+
+impl<Codec> Receiver<Codec> {
+    pub fn new(reader: OwnedReadHalf, tx: &Sender<Codec>) -> Self {
         Self {
             reader,
+            writer: tx.writer.clone(),
             buf: BytesMut::new(),
+            codec: Ws::new(),
+            keepalive: None,
+            error: None,
+            close: None,
+            protocol: None,
+            extensions: Extensions::new(),
+            terminated: false,
             _marker: PhantomData,
         }
     }
 
+    /// The subprotocol agreed on during the upgrade, see
+    /// `WsConfig::protocols`.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    pub(crate) fn set_protocol(&mut self, protocol: String) {
+        self.protocol = Some(protocol);
+    }
+
+    /// Values carried over from the upgrade request, such as `ConnInfo`.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    pub(crate) fn set_inflater(&mut self, inflater: Inflater) {
+        self.codec.set_inflater(inflater);
+    }
+
+    pub(crate) fn set_limits(&mut self, max_frame: usize, max_message: usize) {
+        self.codec.set_limits(max_frame, max_message);
+    }
+
+    /// Frames the peer sent right behind its upgrade request, read before
+    /// the connection was split.
+    pub(crate) fn extend_buf(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Sends a ping whenever nothing has been received for `interval`, and
+    /// fails the connection if the peer then stays silent for `timeout`.
+    pub(crate) fn set_keepalive(&mut self, interval: Duration, timeout: Duration) {
+        self.keepalive = Some(Keepalive {
+            interval,
+            timeout,
+            sleep: Box::pin(time::sleep(interval)),
+            ping_sent: false,
+        });
+    }
+
+    /// Reads the next frame, answering pings along the way. Once the peer
+    /// violates the protocol every following call fails with the same
+    /// error.
     pub async fn next(&mut self) -> std::io::Result<WsFrame> {
-        let mut ws = Ws::new();
+        future::poll_fn(|cx| self.poll_next_frame(cx)).await
+    }
 
-        self.reader.read_buf(&mut self.buf).await?;
+    /// Reads the next complete text, binary or close message. Fragmented
+    /// messages are reassembled, pings are answered and pongs consumed.
+    pub async fn recv(&mut self) -> std::io::Result<Message> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
 
-        let mut res = ws.decode(&mut self.buf);
+    /// Polling counterpart of `next`.
+    pub fn poll_next_frame(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<std::io::Result<WsFrame>> {
+        let frame = ready!(self.poll_read(cx, Ws::decode, true))?;
 
-        while let Ok(None) = res {
-            self.reader.read_buf(&mut self.buf).await?;
-            res = ws.decode(&mut self.buf);
+        if frame.opcode() == &Opcode::CLOSE {
+            match CloseFrame::parse(frame.data()) {
+                Ok(close) => self.close = Some(close),
+                Err(err) => self.error = Some(err),
+            }
         }
 
-        self.buf.clear();
+        Poll::Ready(Ok(frame))
+    }
+
+    /// Polling counterpart of `recv`.
+    pub fn poll_recv(&mut self, cx: &mut task::Context<'_>) -> Poll<std::io::Result<Message>> {
+        let msg = ready!(self.poll_read(cx, Ws::decode_message, false))?;
 
-        match res.unwrap() {
-            Some(msg) => return Ok(msg),
-            _ => unreachable!(),
+        if msg.opcode() == &Opcode::CLOSE {
+            self.close = msg.close_frame();
         }
+
+        Poll::Ready(Ok(msg))
+    }
+
+    fn poll_read<T: Inbound>(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        decode: fn(&mut Ws, &mut BytesMut) -> Result<Option<T>, WsError>,
+        control: bool,
+    ) -> Poll<std::io::Result<T>> {
+        if let Some(err) = &self.error {
+            return Poll::Ready(Err(err.clone().into()));
+        }
+
+        loop {
+            match decode(&mut self.codec, &mut self.buf) {
+                Ok(Some(item)) => match item.opcode() {
+                    Opcode::PING => {
+                        let pong = WsFrame::builder().pong_with(item.data());
+                        self.write_control(pong);
+
+                        if control {
+                            return Poll::Ready(Ok(item));
+                        }
+                    }
+                    Opcode::PONG if !control => {}
+                    _ => return Poll::Ready(Ok(item)),
+                },
+                Ok(None) => ready!(self.poll_fill(cx))?,
+                Err(err) => {
+                    self.error = Some(err.clone());
+                    return Poll::Ready(Err(err.into()));
+                }
+            }
+        }
+    }
+
+    /// Reads more bytes into the buffer, sending keepalive pings while the
+    /// peer is idle.
+    fn poll_fill(&mut self, cx: &mut task::Context<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            let read = self.reader.read_buf(&mut self.buf);
+            tokio::pin!(read);
+
+            if let Poll::Ready(n) = read.poll(cx) {
+                if let Some(keepalive) = &mut self.keepalive {
+                    keepalive.ping_sent = false;
+                    let deadline = Instant::now() + keepalive.interval;
+                    keepalive.sleep.as_mut().reset(deadline);
+                }
+
+                return Poll::Ready(match n? {
+                    0 => Err(std::io::ErrorKind::UnexpectedEof.into()),
+                    _ => Ok(()),
+                });
+            }
+
+            let keepalive = match &mut self.keepalive {
+                Some(keepalive) => keepalive,
+                None => return Poll::Pending,
+            };
+
+            ready!(keepalive.sleep.as_mut().poll(cx));
+
+            if keepalive.ping_sent {
+                self.error = Some(WsError::Timeout);
+                return Poll::Ready(Err(WsError::Timeout.into()));
+            }
+
+            keepalive.ping_sent = true;
+            let deadline = Instant::now() + keepalive.timeout;
+            keepalive.sleep.as_mut().reset(deadline);
+
+            self.write_control(WsFrame::builder().ping());
+        }
+    }
+
+    fn write_control(&self, frame: WsFrame) {
+        if !self.writer.closed.load(Ordering::Acquire) {
+            self.writer.write_control(frame);
+        }
+    }
+
+    /// The close frame received from the peer, if any.
+    pub fn close_frame(&self) -> Option<&CloseFrame> {
+        self.close.as_ref()
+    }
+
+    /// The error that failed the connection, if any.
+    pub fn error(&self) -> Option<&WsError> {
+        self.error.as_ref()
+    }
+}
+
+/// Yields messages like `recv` until a close message or an error, which
+/// end the stream.
+impl<Codec> Stream for Receiver<Codec> {
+    type Item = std::io::Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.terminated {
+            return Poll::Ready(None);
+        }
+
+        let res = ready!(this.poll_recv(cx));
+
+        this.terminated = match &res {
+            Ok(msg) => msg.opcode() == &Opcode::CLOSE,
+            Err(_) => true,
+        };
+
+        Poll::Ready(Some(res))
+    }
+}
+
+trait Inbound {
+    fn opcode(&self) -> &Opcode;
+    fn data(&self) -> &[u8];
+}
+
+impl Inbound for WsFrame {
+    fn opcode(&self) -> &Opcode {
+        WsFrame::opcode(self)
+    }
+
+    fn data(&self) -> &[u8] {
+        WsFrame::data(self)
+    }
+}
+
+impl Inbound for Message {
+    fn opcode(&self) -> &Opcode {
+        Message::opcode(self)
+    }
+
+    fn data(&self) -> &[u8] {
+        Message::data(self)
     }
 }
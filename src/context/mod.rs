@@ -1,5 +1,17 @@
 mod body;
+mod client;
+mod conn;
 mod context;
+mod queue;
 
 pub use body::Body;
+pub use client::{Cidr, CidrError, ClientInfo, TrustedProxies};
+pub(crate) use conn::next_id;
+pub use conn::ConnInfo;
 pub use context::*;
+pub use queue::{Overflow, QueueStats};
+
+#[cfg(test)]
+mod client_test;
+#[cfg(test)]
+mod queue_test;
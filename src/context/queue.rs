@@ -0,0 +1,274 @@
+use crate::codec::websocket::{CloseCode, Opcode, WsFrame};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// What a websocket `Sender` does when its send queue is full.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Overflow {
+    /// Waits until the queue has room.
+    Block,
+    /// Discards the oldest queued message to make room.
+    DropOldest,
+    /// Discards the message being sent.
+    DropNewest,
+    /// Discards everything queued and closes the connection with
+    /// `CloseCode::Policy`. The send fails.
+    Disconnect,
+}
+
+/// A snapshot of the send queue of a websocket connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct QueueStats {
+    depth: usize,
+    capacity: usize,
+    high_water: usize,
+    sent: u64,
+    dropped: u64,
+}
+
+impl QueueStats {
+    /// Messages waiting to be written.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The largest depth the queue has reached.
+    pub fn high_water(&self) -> usize {
+        self.high_water
+    }
+
+    /// Frames written to the connection.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Messages discarded by the overflow policy.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Frames waiting to be written by the task a `Sender` spawns to drain its
+/// queue. Control frames are never dropped and do not count
+/// against the capacity.
+pub(crate) struct SendQueue {
+    state: Mutex<State>,
+    capacity: usize,
+    overflow: Overflow,
+    /// Wakes the draining task when a frame is queued.
+    queued: Notify,
+    /// Wakes senders when a frame was written or the queue failed.
+    written: Notify,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[derive(Default)]
+struct State {
+    frames: VecDeque<(WsFrame, bool)>,
+    data: usize,
+    writing: bool,
+    closing: bool,
+    error: Option<io::ErrorKind>,
+    high_water: usize,
+    sent: u64,
+    dropped: u64,
+}
+
+impl SendQueue {
+    pub(crate) fn new(capacity: usize, overflow: Overflow) -> Self {
+        assert!(capacity > 0, "send queue capacity must be positive");
+
+        Self {
+            state: Mutex::new(State::default()),
+            capacity,
+            overflow,
+            queued: Notify::new(),
+            written: Notify::new(),
+            task: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn set_task(&self, task: JoinHandle<()>) {
+        *self.task.lock().unwrap() = Some(task);
+    }
+
+    /// Queues a frame, applying the overflow policy once the queue is full.
+    pub(crate) async fn push(&self, frame: WsFrame, compress: bool) -> io::Result<()> {
+        let control = frame.opcode().is_control();
+
+        loop {
+            let written = self.written.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                state.check()?;
+
+                if control || state.data < self.capacity {
+                    state.push(frame, compress);
+                    break;
+                }
+
+                match self.overflow {
+                    Overflow::Block => {}
+                    Overflow::DropOldest => {
+                        state.drop_oldest();
+                        state.push(frame, compress);
+                        break;
+                    }
+                    Overflow::DropNewest => {
+                        state.dropped += 1;
+                        return Ok(());
+                    }
+                    Overflow::Disconnect => {
+                        let dropped = state.data as u64 + 1;
+                        state.frames.retain(|(f, _)| f.opcode().is_control());
+                        state.data = 0;
+                        state.dropped += dropped;
+
+                        let close =
+                            WsFrame::builder().close_with(CloseCode::Policy, "send queue full");
+                        state.push(close, false);
+                        drop(state);
+                        self.queued.notify_one();
+
+                        return Err(io::Error::other("websocket send queue full"));
+                    }
+                }
+            }
+
+            written.await;
+        }
+
+        self.queued.notify_one();
+        Ok(())
+    }
+
+    /// Takes the next frame to write, waiting for one to be queued.
+    pub(crate) async fn pop(&self) -> (WsFrame, bool) {
+        loop {
+            let queued = self.queued.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some((frame, compress)) = state.frames.pop_front() {
+                    if !frame.opcode().is_control() {
+                        state.data -= 1;
+                    }
+                    state.writing = true;
+                    return (frame, compress);
+                }
+            }
+
+            queued.await;
+        }
+    }
+
+    /// Records the outcome of writing the frame last taken by `pop`.
+    pub(crate) fn written(&self, res: &io::Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        state.writing = false;
+
+        match res {
+            Ok(()) => state.sent += 1,
+            Err(e) => {
+                state.error = Some(e.kind());
+                state.frames.clear();
+                state.data = 0;
+            }
+        }
+
+        drop(state);
+        self.written.notify_waiters();
+    }
+
+    /// Waits until every queued frame has been written.
+    pub(crate) async fn flush(&self) -> io::Result<()> {
+        loop {
+            let written = self.written.notified();
+
+            {
+                let state = self.state.lock().unwrap();
+                if let Some(kind) = state.error {
+                    return Err(kind.into());
+                }
+
+                if state.frames.is_empty() && !state.writing {
+                    return Ok(());
+                }
+            }
+
+            written.await;
+        }
+    }
+
+    /// Whether a close frame was queued.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.state.lock().unwrap().closing
+    }
+
+    /// Stops the draining task, dropping whatever it has not written.
+    pub(crate) fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+
+        QueueStats {
+            depth: state.frames.len(),
+            capacity: self.capacity,
+            high_water: state.high_water,
+            sent: state.sent,
+            dropped: state.dropped,
+        }
+    }
+}
+
+impl State {
+    fn check(&self) -> io::Result<()> {
+        if let Some(kind) = self.error {
+            return Err(kind.into());
+        }
+
+        if self.closing {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "websocket close frame already sent",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, frame: WsFrame, compress: bool) {
+        match frame.opcode() {
+            Opcode::CLOSE => self.closing = true,
+            opcode if opcode.is_control() => {}
+            _ => self.data += 1,
+        }
+
+        self.frames.push_back((frame, compress));
+        self.high_water = self.high_water.max(self.frames.len());
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(i) = self
+            .frames
+            .iter()
+            .position(|(f, _)| !f.opcode().is_control())
+        {
+            self.frames.remove(i);
+            self.data -= 1;
+            self.dropped += 1;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::codec::websocket::{Message, Opcode, Ws};
+use crate::context::{Overflow, Sender};
+use crate::loopback::{connect, text, WsPeer};
+
+async fn queued(capacity: usize, overflow: Overflow) -> (WsPeer, Sender<Ws>) {
+    let (client, mut tx, _) = connect().await;
+    tx.set_queue(capacity, overflow);
+    (client, tx)
+}
+
+// the test runtime runs the draining task only once the test yields, so
+// sends fill the queue until then
+
+#[tokio::test]
+async fn queue_drops_newest_or_oldest() {
+    let (mut client, mut tx) = queued(2, Overflow::DropNewest).await;
+    for msg in ["a", "b", "c"] {
+        tx.send(Message::text(msg)).await.unwrap();
+    }
+
+    let stats = tx.queue_stats().unwrap();
+    assert_eq!((stats.depth(), stats.dropped()), (2, 1));
+
+    tx.flush().await.unwrap();
+    assert_eq!(client.read_frames(2).await, [text("a"), text("b")]);
+
+    let stats = tx.queue_stats().unwrap();
+    assert_eq!((stats.depth(), stats.sent(), stats.high_water()), (0, 2, 2));
+
+    let (mut client, mut tx) = queued(2, Overflow::DropOldest).await;
+    for msg in ["a", "b", "c"] {
+        tx.send(Message::text(msg)).await.unwrap();
+    }
+    // control frames are never dropped
+    tx.send(Message::ping("p")).await.unwrap();
+
+    tx.flush().await.unwrap();
+    assert_eq!(
+        client.read_frames(3).await,
+        [text("b"), text("c"), (Opcode::PING, b"p".to_vec())]
+    );
+}
+
+#[tokio::test]
+async fn queue_blocks_until_drained() {
+    let (mut client, mut tx) = queued(1, Overflow::Block).await;
+    for msg in ["a", "b", "c"] {
+        tx.send(Message::text(msg)).await.unwrap();
+    }
+
+    assert_eq!(
+        client.read_frames(3).await,
+        [text("a"), text("b"), text("c")]
+    );
+    assert_eq!(tx.queue_stats().unwrap().dropped(), 0);
+}
+
+#[tokio::test]
+async fn queue_disconnects_slow_clients() {
+    let (mut client, mut tx) = queued(2, Overflow::Disconnect).await;
+    tx.send(Message::text("a")).await.unwrap();
+    tx.send(Message::text("b")).await.unwrap();
+    assert!(tx.send(Message::text("c")).await.is_err());
+    assert!(tx.is_closed());
+    assert_eq!(tx.queue_stats().unwrap().dropped(), 3);
+
+    // the queued messages are discarded, only the close frame goes out
+    let frames = client.read_frames(1).await;
+    assert_eq!(frames[0].0, Opcode::CLOSE);
+    assert_eq!(&frames[0].1[..2], &1008u16.to_be_bytes());
+    assert!(tx.send(Message::text("d")).await.is_err());
+}
@@ -0,0 +1,376 @@
+use crate::codec::websocket::{CloseCode, Message, Ws};
+use crate::context::{next_id, ConnInfo, Receiver, Sender};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
+
+/// What the hub does with a client whose queue of outgoing messages is full,
+/// because it reads slower than messages are published.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SlowConsumer {
+    /// Drops the messages that do not fit, the client misses them.
+    Drop,
+    /// Removes the client from the hub and closes its connection with
+    /// `CloseCode::Policy`.
+    Disconnect,
+    /// Queues every message, the capacity is ignored.
+    Buffer,
+}
+
+/// Publish/subscribe hub for websocket connections.
+///
+/// Connections register with `connect` and join named rooms, messages are
+/// then published to a room, to every client or to one client by its ID.
+/// Each client has its own queue and a task writing it to the connection, so
+/// publishing never waits on the network. The hub is cheap to clone and can
+/// be shared between routes and worker threads.
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<Mutex<HubInner>>,
+    capacity: usize,
+    slow_consumer: SlowConsumer,
+}
+
+#[derive(Default)]
+struct HubInner {
+    clients: HashMap<u64, Client>,
+    rooms: HashMap<String, BTreeSet<u64>>,
+    /// Counts registrations, telling apart the `Member`s of an ID connected
+    /// more than once.
+    generation: u64,
+}
+
+struct Client {
+    generation: u64,
+    queue: Queue,
+    evicted: Arc<AtomicBool>,
+    rooms: BTreeSet<String>,
+}
+
+enum Queue {
+    Bounded(mpsc::Sender<Message>),
+    Unbounded(mpsc::UnboundedSender<Message>),
+}
+
+enum QueueRx {
+    Bounded(mpsc::Receiver<Message>),
+    Unbounded(mpsc::UnboundedReceiver<Message>),
+}
+
+impl QueueRx {
+    async fn recv(&mut self) -> Option<Message> {
+        match self {
+            Self::Bounded(rx) => rx.recv().await,
+            Self::Unbounded(rx) => rx.recv().await,
+        }
+    }
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HubInner::default())),
+            capacity: 64,
+            slow_consumer: SlowConsumer::Drop,
+        }
+    }
+
+    /// How many messages may wait for each client, 64 by default.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "hub capacity must be positive");
+        self.capacity = capacity;
+        self
+    }
+
+    /// What happens to clients that fall behind, `SlowConsumer::Drop` by
+    /// default.
+    pub fn slow_consumer(mut self, policy: SlowConsumer) -> Self {
+        self.slow_consumer = policy;
+        self
+    }
+
+    /// Registers a connection. Its ID is the one of its `ConnInfo`, and the
+    /// client stays registered until the returned `Member` is dropped.
+    /// Connecting an ID again replaces the client, the `Member` returned
+    /// before no longer has any effect on the hub.
+    ///
+    /// Must be called from within the handler of the connection, as the
+    /// task writing to it is spawned on the current runtime.
+    pub fn connect(&self, tx: &Sender<Ws>, rx: &Receiver<Ws>) -> Member {
+        let id = match rx.extensions().get::<ConnInfo>() {
+            Some(info) => info.id(),
+            None => next_id(),
+        };
+
+        let (queue, queue_rx) = match self.slow_consumer {
+            SlowConsumer::Buffer => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                (Queue::Unbounded(tx), QueueRx::Unbounded(rx))
+            }
+            _ => {
+                let (tx, rx) = mpsc::channel(self.capacity);
+                (Queue::Bounded(tx), QueueRx::Bounded(rx))
+            }
+        };
+
+        let evicted = Arc::new(AtomicBool::new(false));
+        tokio::spawn(forward(tx.clone(), queue_rx, evicted.clone()));
+
+        let mut inner = self.lock();
+        inner.generation += 1;
+
+        let client = Client {
+            generation: inner.generation,
+            queue,
+            evicted,
+            rooms: BTreeSet::new(),
+        };
+
+        inner.remove(id);
+        inner.clients.insert(id, client);
+
+        Member {
+            id,
+            generation: inner.generation,
+            hub: self.clone(),
+        }
+    }
+
+    /// Queues a message for every client in `room`, returning how many it
+    /// was queued for.
+    pub fn publish(&self, room: &str, msg: Message) -> usize {
+        let mut inner = self.lock();
+        let ids: Vec<u64> = match inner.rooms.get(room) {
+            Some(members) => members.iter().copied().collect(),
+            None => return 0,
+        };
+
+        self.deliver(&mut inner, &ids, msg)
+    }
+
+    /// Queues a message for every connected client.
+    pub fn broadcast(&self, msg: Message) -> usize {
+        let mut inner = self.lock();
+        let ids: Vec<u64> = inner.clients.keys().copied().collect();
+
+        self.deliver(&mut inner, &ids, msg)
+    }
+
+    /// Queues a message for one client. Returns false if it is not
+    /// connected or the message was not queued.
+    pub fn send_to(&self, id: u64, msg: Message) -> bool {
+        let mut inner = self.lock();
+        self.deliver(&mut inner, &[id], msg) == 1
+    }
+
+    /// The IDs of the clients in `room`.
+    pub fn members(&self, room: &str) -> Vec<u64> {
+        match self.lock().rooms.get(room) {
+            Some(members) => members.iter().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The rooms that have at least one client.
+    pub fn rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<_> = self.lock().rooms.keys().cloned().collect();
+        rooms.sort();
+        rooms
+    }
+
+    pub fn is_connected(&self, id: u64) -> bool {
+        self.lock().clients.contains_key(&id)
+    }
+
+    /// Number of connected clients.
+    pub fn len(&self) -> usize {
+        self.lock().clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().clients.is_empty()
+    }
+
+    fn deliver(&self, inner: &mut HubInner, ids: &[u64], msg: Message) -> usize {
+        let mut queued = 0;
+        let mut evicted = Vec::new();
+
+        for id in ids {
+            let client = match inner.clients.get(id) {
+                Some(client) => client,
+                None => continue,
+            };
+
+            let full = match &client.queue {
+                Queue::Bounded(queue) => match queue.try_send(msg.clone()) {
+                    Ok(()) => false,
+                    Err(mpsc::error::TrySendError::Full(_)) => true,
+                    Err(mpsc::error::TrySendError::Closed(_)) => continue,
+                },
+                Queue::Unbounded(queue) => match queue.send(msg.clone()) {
+                    Ok(()) => false,
+                    Err(_) => continue,
+                },
+            };
+
+            if !full {
+                queued += 1;
+            } else if self.slow_consumer == SlowConsumer::Disconnect {
+                client.evicted.store(true, Ordering::Release);
+                evicted.push(*id);
+            }
+        }
+
+        for id in evicted {
+            log::debug!("hub client {} disconnected, queue full", id);
+            inner.remove(id);
+        }
+
+        queued
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HubInner> {
+        // the lock is never held across user code, a poisoned hub is still
+        // consistent
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl HubInner {
+    /// The client registered by a `Member`, unless it left or was replaced.
+    fn member(&mut self, id: u64, generation: u64) -> Option<&mut Client> {
+        self.clients
+            .get_mut(&id)
+            .filter(|client| client.generation == generation)
+    }
+
+    fn remove(&mut self, id: u64) {
+        let client = match self.clients.remove(&id) {
+            Some(client) => client,
+            None => return,
+        };
+
+        for room in client.rooms {
+            if let Some(members) = self.rooms.get_mut(&room) {
+                members.remove(&id);
+                if members.is_empty() {
+                    self.rooms.remove(&room);
+                }
+            }
+        }
+    }
+}
+
+/// Writes the queued messages of a client until it leaves the hub, closing
+/// the connection if it was evicted.
+async fn forward(mut tx: Sender<Ws>, mut queue: QueueRx, evicted: Arc<AtomicBool>) {
+    while let Some(msg) = queue.recv().await {
+        if evicted.load(Ordering::Acquire) || tx.send(msg).await.is_err() {
+            break;
+        }
+    }
+
+    if evicted.load(Ordering::Acquire) && !tx.is_closed() {
+        let _ = tx.close(CloseCode::Policy, "slow consumer").await;
+    }
+}
+
+/// A connection registered with a `Hub`, removed from the hub and all its
+/// rooms when dropped.
+pub struct Member {
+    id: u64,
+    generation: u64,
+    hub: Hub,
+}
+
+impl Member {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn hub(&self) -> &Hub {
+        &self.hub
+    }
+
+    /// Joins a room, creating it if needed. Does nothing if the client was
+    /// disconnected by the hub.
+    pub fn join(&self, room: &str) {
+        let mut inner = self.hub.lock();
+        let client = match inner.member(self.id, self.generation) {
+            Some(client) => client,
+            None => return,
+        };
+
+        if client.rooms.insert(room.to_string()) {
+            inner
+                .rooms
+                .entry(room.to_string())
+                .or_default()
+                .insert(self.id);
+        }
+    }
+
+    /// Leaves a room, removing it once it is empty.
+    pub fn leave(&self, room: &str) {
+        let mut inner = self.hub.lock();
+        let client = match inner.member(self.id, self.generation) {
+            Some(client) => client,
+            None => return,
+        };
+
+        if client.rooms.remove(room) {
+            if let Some(members) = inner.rooms.get_mut(room) {
+                members.remove(&self.id);
+                if members.is_empty() {
+                    inner.rooms.remove(room);
+                }
+            }
+        }
+    }
+
+    /// The rooms the client is in.
+    pub fn rooms(&self) -> Vec<String> {
+        match self.hub.lock().member(self.id, self.generation) {
+            Some(client) => client.rooms.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether the client is still registered, it is not once the hub
+    /// disconnected it as a slow consumer or its ID connected again.
+    pub fn is_connected(&self) -> bool {
+        self.hub.lock().member(self.id, self.generation).is_some()
+    }
+
+    /// Queues a message for every client in `room` except this one.
+    pub fn publish(&self, room: &str, msg: Message) -> usize {
+        let mut inner = self.hub.lock();
+        let ids: Vec<u64> = match inner.rooms.get(room) {
+            Some(members) => members
+                .iter()
+                .copied()
+                .filter(|id| *id != self.id)
+                .collect(),
+            None => return 0,
+        };
+
+        self.hub.deliver(&mut inner, &ids, msg)
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let mut inner = self.hub.lock();
+
+        if inner.member(self.id, self.generation).is_some() {
+            inner.remove(self.id);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::codec::websocket::{Message, Opcode};
+use crate::context::ConnInfo;
+use crate::hub::{Hub, SlowConsumer};
+use crate::loopback::{connect, text};
+
+#[tokio::test]
+async fn hub_rooms_and_presence() {
+    let hub = Hub::new();
+    let (mut a, a_tx, a_rx) = connect().await;
+    let (mut b, b_tx, b_rx) = connect().await;
+
+    let alice = hub.connect(&a_tx, &a_rx);
+    let bob = hub.connect(&b_tx, &b_rx);
+    assert_ne!(alice.id(), bob.id());
+    assert_eq!(hub.len(), 2);
+
+    alice.join("lobby");
+    alice.join("games");
+    bob.join("lobby");
+    assert_eq!(hub.rooms(), ["games", "lobby"]);
+    assert_eq!(hub.members("lobby").len(), 2);
+    assert_eq!(alice.rooms(), ["games", "lobby"]);
+
+    assert_eq!(hub.publish("lobby", Message::text("hi")), 2);
+    assert_eq!(hub.publish("games", Message::text("gg")), 1);
+    assert_eq!(bob.publish("lobby", Message::text("yo")), 1);
+    assert!(hub.send_to(bob.id(), Message::text("dm")));
+    assert_eq!(hub.publish("nowhere", Message::text("?")), 0);
+
+    assert_eq!(a.read_frames(3).await, [text("hi"), text("gg"), text("yo")]);
+    assert_eq!(b.read_frames(2).await, [text("hi"), text("dm")]);
+
+    alice.leave("games");
+    assert_eq!(hub.rooms(), ["lobby"]);
+
+    let id = bob.id();
+    drop(bob);
+    assert!(!hub.is_connected(id));
+    assert!(!hub.send_to(id, Message::text("gone")));
+    assert_eq!(hub.members("lobby"), [alice.id()]);
+}
+
+#[tokio::test]
+async fn hub_slow_consumers() {
+    // nothing is written until the test yields, so the queues fill up
+    let hub = Hub::new().capacity(1);
+    let (mut client, tx, rx) = connect().await;
+    let member = hub.connect(&tx, &rx);
+    member.join("room");
+
+    assert_eq!(hub.publish("room", Message::text("a")), 1);
+    assert_eq!(hub.publish("room", Message::text("b")), 0);
+    assert!(member.is_connected());
+    assert_eq!(client.read_frames(1).await, [text("a")]);
+
+    let hub = Hub::new()
+        .capacity(1)
+        .slow_consumer(SlowConsumer::Disconnect);
+    let (mut client, tx, rx) = connect().await;
+    let member = hub.connect(&tx, &rx);
+    member.join("room");
+
+    assert_eq!(hub.publish("room", Message::text("a")), 1);
+    assert_eq!(hub.publish("room", Message::text("b")), 0);
+    assert!(!member.is_connected());
+    assert!(hub.rooms().is_empty());
+
+    // 1008 with the reason
+    let frames = client.read_frames(1).await;
+    assert_eq!(frames[0].0, Opcode::CLOSE);
+    assert_eq!(&frames[0].1[..2], &1008u16.to_be_bytes());
+    assert!(tx.is_closed());
+
+    let hub = Hub::new().capacity(1).slow_consumer(SlowConsumer::Buffer);
+    let (mut client, tx, rx) = connect().await;
+    let member = hub.connect(&tx, &rx);
+    member.join("room");
+
+    for msg in ["a", "b", "c"] {
+        assert_eq!(hub.publish("room", Message::text(msg)), 1);
+    }
+    assert_eq!(client.read_frames(3).await.len(), 3);
+}
+
+#[tokio::test]
+async fn hub_reconnected_id_outlives_old_member() {
+    let hub = Hub::new();
+    let (_client, tx, mut rx) = connect().await;
+    let addr = "127.0.0.1:1".parse().unwrap();
+    rx.extensions_mut().insert(ConnInfo::new(addr, addr));
+
+    let old = hub.connect(&tx, &rx);
+    old.join("lobby");
+    let new = hub.connect(&tx, &rx);
+    assert_eq!(old.id(), new.id());
+    assert!(!old.is_connected());
+    assert!(old.rooms().is_empty());
+
+    // the stale member leaves the registration that replaced it alone
+    new.join("games");
+    old.join("lobby");
+    drop(old);
+    assert!(new.is_connected());
+    assert_eq!(new.rooms(), ["games"]);
+    assert_eq!(hub.members("games"), [new.id()]);
+}
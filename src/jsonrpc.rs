@@ -0,0 +1,362 @@
+//! JSON-RPC 2.0 over websockets and HTTP.
+//!
+//! Methods are registered by name on an `Rpc` dispatcher, which is then
+//! mounted with `Router::post`, `Router::ws`, or both.
+
+use crate::codec::websocket::{Message, Opcode, Ws};
+use crate::context::{Body, Receiver, Sender};
+use crate::routing::{HttpRoute, Route};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use http::header::CONTENT_TYPE;
+use http::{Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use trie_rs::params;
+
+type RpcOutput = Pin<Box<dyn Future<Output = Result<Value, RpcError>> + Send>>;
+type Handler = dyn Fn(Value, Peer) -> RpcOutput + Send + Sync;
+
+/// Dispatches JSON-RPC requests, single or batched, to the methods
+/// registered on it.
+///
+/// Requests on a websocket are handled one after the other, methods that
+/// take long should spawn a task and report back with `Peer::notify`.
+#[derive(Clone, Default)]
+pub struct Rpc {
+    methods: HashMap<String, Arc<Handler>>,
+}
+
+impl Rpc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a method. Its params are decoded into a `P`, absent params
+    /// as `null`, and failing that the call is answered with
+    /// `RpcError::INVALID_PARAMS`.
+    pub fn method<F, Fut, P, R>(self, name: &str, f: F) -> Self
+    where
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, RpcError>> + Send + 'static,
+        P: DeserializeOwned,
+        R: Serialize,
+    {
+        self.method_with(name, move |params, _peer| f(params))
+    }
+
+    /// Like `method`, also passing the peer that made the call, to send it
+    /// notifications.
+    pub fn method_with<F, Fut, P, R>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(P, Peer) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, RpcError>> + Send + 'static,
+        P: DeserializeOwned,
+        R: Serialize,
+    {
+        let handler = move |params: Value, peer: Peer| -> RpcOutput {
+            let params = match serde_json::from_value(params) {
+                Ok(params) => params,
+                Err(e) => return Box::pin(async move { Err(RpcError::invalid_params(e)) }),
+            };
+
+            let call = f(params, peer);
+
+            Box::pin(async move {
+                let result = call.await?;
+                serde_json::to_value(result).map_err(RpcError::internal_error)
+            })
+        };
+
+        self.methods.insert(name.to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Handles a request or batch read from some other transport, returning
+    /// the response to send back, if any. Notifications are not answered.
+    pub async fn respond(&self, body: &[u8]) -> Option<String> {
+        self.dispatch(body, Peer { tx: None })
+            .await
+            .map(|response| response.to_string())
+    }
+
+    async fn dispatch(&self, body: &[u8], peer: Peer) -> Option<Value> {
+        let request: Value = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, RpcError::parse_error(e))),
+        };
+
+        let batch = match request {
+            Value::Array(batch) if batch.is_empty() => {
+                let err = RpcError::invalid_request("empty batch");
+                return Some(error_response(Value::Null, err));
+            }
+            Value::Array(batch) => batch,
+            request => return self.call(request, peer).await,
+        };
+
+        let mut responses = Vec::new();
+
+        for request in batch {
+            if let Some(response) = self.call(request, peer.clone()).await {
+                responses.push(response);
+            }
+        }
+
+        match responses.is_empty() {
+            true => None,
+            false => Some(Value::Array(responses)),
+        }
+    }
+
+    /// Runs a single request, returning its response unless it is a
+    /// notification.
+    async fn call(&self, request: Value, peer: Peer) -> Option<Value> {
+        let mut request = match request {
+            Value::Object(request) => request,
+            _ => {
+                let err = RpcError::invalid_request("request is not an object");
+                return Some(error_response(Value::Null, err));
+            }
+        };
+
+        let id = request.remove("id");
+
+        let (method, params) = match parse_request(&mut request, &id) {
+            Ok(request) => request,
+            Err(err) => return Some(error_response(id.unwrap_or(Value::Null), err)),
+        };
+
+        let result = match self.methods.get(&method) {
+            Some(handler) => handler(params, peer).await,
+            None => Err(RpcError::method_not_found(&method)),
+        };
+
+        let id = id?;
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(err) => error_response(id, err),
+        })
+    }
+}
+
+fn parse_request(
+    request: &mut Map<String, Value>,
+    id: &Option<Value>,
+) -> Result<(String, Value), RpcError> {
+    if request.get("jsonrpc") != Some(&Value::String("2.0".to_string())) {
+        return Err(RpcError::invalid_request("jsonrpc must be \"2.0\""));
+    }
+
+    if !matches!(
+        id,
+        None | Some(Value::String(_) | Value::Number(_) | Value::Null)
+    ) {
+        return Err(RpcError::invalid_request("id must be a string or number"));
+    }
+
+    let method = match request.remove("method") {
+        Some(Value::String(method)) => method,
+        _ => return Err(RpcError::invalid_request("method must be a string")),
+    };
+
+    match request.remove("params") {
+        None => Ok((method, Value::Null)),
+        Some(params @ (Value::Array(_) | Value::Object(_))) => Ok((method, params)),
+        Some(_) => Err(RpcError::invalid_request(
+            "params must be an array or object",
+        )),
+    }
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": err.to_value(), "id": id })
+}
+
+/// Answers `POST` requests carrying a JSON-RPC request or batch, with `204
+/// No Content` when there is nothing to answer.
+#[async_trait]
+impl HttpRoute for Rpc {
+    async fn handle(
+        &self,
+        req: &Request<Body>,
+        _params: &params::Params,
+    ) -> std::io::Result<Response<Body>> {
+        let mut body = BytesMut::new();
+        req.body().bytes(&mut body);
+
+        let resp = match self.dispatch(&body, Peer { tx: None }).await {
+            Some(response) => Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "application/json")
+                .body(response.to_string().into()),
+            None => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(().into()),
+        };
+
+        Ok(resp.unwrap())
+    }
+}
+
+/// Answers every text or binary message carrying a JSON-RPC request or batch
+/// with a text message, until the connection closes.
+#[async_trait]
+impl Route<Ws> for Rpc {
+    async fn handle(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+        _params: &params::Params,
+    ) -> std::io::Result<()> {
+        self.run(tx, rx).await
+    }
+}
+
+impl Rpc {
+    pub(crate) async fn run(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+    ) -> std::io::Result<()> {
+        loop {
+            let msg = rx.recv().await?;
+
+            match msg.opcode() {
+                Opcode::TEXT | Opcode::BINARY => {}
+                Opcode::CLOSE => return Ok(()),
+                _ => continue,
+            }
+
+            let peer = Peer {
+                tx: Some(tx.clone()),
+            };
+
+            if let Some(response) = self.dispatch(msg.data(), peer).await {
+                tx.send(Message::text(response.to_string())).await?;
+            }
+        }
+    }
+}
+
+/// The client that made a call.
+#[derive(Clone)]
+pub struct Peer {
+    tx: Option<Sender<Ws>>,
+}
+
+impl Peer {
+    /// Whether the call arrived over a websocket, and so notifications can
+    /// be sent.
+    pub fn is_websocket(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Sends a notification to the peer. Fails over HTTP, where the server
+    /// can only answer.
+    pub async fn notify<T: Serialize>(&mut self, method: &str, params: &T) -> std::io::Result<()> {
+        let msg = notification(method, params)?;
+
+        match &mut self.tx {
+            Some(tx) => tx.send(msg).await,
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "notifications need a websocket",
+            )),
+        }
+    }
+}
+
+/// Builds a notification message, e.g. to publish through a `Hub`.
+pub fn notification<T: Serialize>(method: &str, params: &T) -> std::io::Result<Message> {
+    let params = serde_json::to_value(params)?;
+    let notification = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+    Ok(Message::text(notification.to_string()))
+}
+
+/// An error answering a call, with one of the codes reserved by the
+/// specification or an application defined one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Attaches additional information about the error.
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn parse_error(err: impl fmt::Display) -> Self {
+        Self::new(Self::PARSE_ERROR, "Parse error").with_data(err.to_string().into())
+    }
+
+    pub fn invalid_request(err: impl fmt::Display) -> Self {
+        Self::new(Self::INVALID_REQUEST, "Invalid Request").with_data(err.to_string().into())
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(Self::METHOD_NOT_FOUND, "Method not found").with_data(method.into())
+    }
+
+    pub fn invalid_params(err: impl fmt::Display) -> Self {
+        Self::new(Self::INVALID_PARAMS, "Invalid params").with_data(err.to_string().into())
+    }
+
+    pub fn internal_error(err: impl fmt::Display) -> Self {
+        Self::new(Self::INTERNAL_ERROR, "Internal error").with_data(err.to_string().into())
+    }
+
+    pub fn code(&self) -> i64 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
+
+    fn to_value(&self) -> Value {
+        let mut err = json!({ "code": self.code, "message": self.message });
+
+        if let Some(data) = &self.data {
+            err["data"] = data.clone();
+        }
+
+        err
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
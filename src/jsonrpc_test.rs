@@ -0,0 +1,157 @@
+use crate::codec::websocket::WsFrame;
+use crate::jsonrpc::{Peer, Rpc, RpcError};
+use crate::loopback::connect;
+use serde_json::{json, Value};
+
+fn rpc() -> Rpc {
+    Rpc::new()
+        .method("add", |(a, b): (i64, i64)| async move { Ok(a + b) })
+        .method("ping", |_: ()| async { Ok("pong") })
+        .method("fail", |_: Value| async {
+            Err::<(), _>(RpcError::new(-32000, "nope").with_data(json!(1)))
+        })
+        .method_with("watch", |(topic,): (String,), mut peer: Peer| async move {
+            peer.notify("changed", &json!({ "topic": topic }))
+                .await
+                .map_err(RpcError::internal_error)?;
+            Ok(true)
+        })
+}
+
+async fn call(body: &str) -> Option<Value> {
+    let response = rpc().respond(body.as_bytes()).await?;
+    Some(serde_json::from_str(&response).unwrap())
+}
+
+fn code(response: &Value) -> i64 {
+    response["error"]["code"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn jsonrpc_calls() {
+    assert_eq!(
+        call(r#"{"jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": 1}"#).await,
+        Some(json!({ "jsonrpc": "2.0", "result": 3, "id": 1 }))
+    );
+    assert_eq!(
+        call(r#"{"jsonrpc": "2.0", "method": "ping", "id": "a"}"#).await,
+        Some(json!({ "jsonrpc": "2.0", "result": "pong", "id": "a" }))
+    );
+    assert_eq!(
+        call(r#"{"jsonrpc": "2.0", "method": "fail", "id": 2}"#).await,
+        Some(json!({
+            "jsonrpc": "2.0",
+            "error": { "code": -32000, "message": "nope", "data": 1 },
+            "id": 2
+        }))
+    );
+
+    // notifications are never answered, even when they fail
+    assert_eq!(
+        call(r#"{"jsonrpc": "2.0", "method": "add", "params": [1, 2]}"#).await,
+        None
+    );
+    assert_eq!(
+        call(r#"{"jsonrpc": "2.0", "method": "missing"}"#).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn jsonrpc_errors() {
+    for (body, expected) in [
+        ("{", RpcError::PARSE_ERROR),
+        ("[]", RpcError::INVALID_REQUEST),
+        ("1", RpcError::INVALID_REQUEST),
+        (r#"{"method": "ping", "id": 1}"#, RpcError::INVALID_REQUEST),
+        (
+            r#"{"jsonrpc": "2.0", "method": 1, "id": 1}"#,
+            RpcError::INVALID_REQUEST,
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "ping", "params": 1, "id": 1}"#,
+            RpcError::INVALID_REQUEST,
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "ping", "id": {}}"#,
+            RpcError::INVALID_REQUEST,
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "missing", "id": 1}"#,
+            RpcError::METHOD_NOT_FOUND,
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "add", "params": ["a"], "id": 1}"#,
+            RpcError::INVALID_PARAMS,
+        ),
+    ] {
+        let response = call(body).await.unwrap();
+        assert_eq!(code(&response), expected, "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn jsonrpc_batches() {
+    let response = call(
+        r#"[
+            {"jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": 1},
+            {"jsonrpc": "2.0", "method": "add", "params": [3, 4]},
+            {"jsonrpc": "2.0", "method": "missing", "id": 2},
+            1
+        ]"#,
+    )
+    .await
+    .unwrap();
+
+    let responses = response.as_array().unwrap();
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0]["result"], 3);
+    assert_eq!(code(&responses[1]), RpcError::METHOD_NOT_FOUND);
+    assert_eq!(responses[2]["id"], Value::Null);
+
+    // a batch of notifications has no response at all
+    let batch = r#"[{"jsonrpc": "2.0", "method": "ping"}, {"jsonrpc": "2.0", "method": "ping"}]"#;
+    assert_eq!(call(batch).await, None);
+}
+
+#[tokio::test]
+async fn jsonrpc_over_websocket() {
+    let (mut client, mut tx, mut rx) = connect().await;
+    client
+        .send([
+            WsFrame::builder()
+                .text(r#"{"jsonrpc": "2.0", "method": "watch", "params": ["a"], "id": 1}"#),
+            WsFrame::builder().close(),
+        ])
+        .await;
+
+    rpc().run(&mut tx, &mut rx).await.unwrap();
+    drop((tx, rx));
+
+    // the notification is sent before the response to the call
+    let messages: Vec<Value> = client
+        .read_to_end()
+        .await
+        .iter()
+        .map(|(_, data)| serde_json::from_slice(data).unwrap())
+        .collect();
+
+    assert_eq!(
+        messages,
+        [
+            json!({ "jsonrpc": "2.0", "method": "changed", "params": { "topic": "a" } }),
+            json!({ "jsonrpc": "2.0", "result": true, "id": 1 }),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn jsonrpc_fails_on_protocol_errors() {
+    let (mut client, mut tx, mut rx) = connect().await;
+    client
+        .send([WsFrame::builder().continuation("no message to continue")])
+        .await;
+
+    let err = rpc().run(&mut tx, &mut rx).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -1,17 +1,46 @@
+pub mod client;
 pub mod codec;
 pub mod context;
+mod hub;
+#[cfg(feature = "jsonrpc")]
+pub mod jsonrpc;
+#[cfg(test)]
+mod loopback;
 pub mod routing;
+mod sse;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod worker;
 mod ws;
 
+#[cfg(test)]
+mod hub_test;
+#[cfg(all(test, feature = "jsonrpc"))]
+mod jsonrpc_test;
+#[cfg(test)]
+mod sse_test;
+#[cfg(all(test, feature = "json"))]
+mod testing_test;
+#[cfg(test)]
+mod ws_test;
+
 pub mod prelude {
+    pub use crate::client::{Client, WsClient};
     pub use crate::codec::{
+        deflate::Deflate,
         http::Http,
-        websocket::{Opcode, Ws, WsFrame, WsFrameBuilder},
+        websocket::{CloseCode, CloseFrame, Message, Opcode, Ws, WsError, WsFrame, WsFrameBuilder},
+    };
+    pub use crate::context::{
+        self, Body, ClientInfo, ConnInfo, Context, Overflow, QueueStats, TrustedProxies,
     };
-    pub use crate::context::{self, Body, Context};
+    pub use crate::hub::{Hub, Member, SlowConsumer};
+    #[cfg(feature = "json")]
+    pub use crate::routing::{json, json_stream, OnDecodeError};
     pub use crate::routing::{wrap, HttpRoute, Route, Router};
+    pub use crate::sse::{Event, Sse};
     pub use crate::worker::serve;
+    pub use crate::ws::WsConfig;
     pub use http::{Method, Request, Response, StatusCode};
     pub use trie_rs::params::Params;
 }
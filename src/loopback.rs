@@ -0,0 +1,85 @@
+//! Websocket connections over the loopback interface, for tests that drive
+//! a `Sender` and `Receiver` from the client end of a real socket.
+
+use crate::codec::websocket::{Opcode, Ws, WsFrame};
+use crate::codec::{Decoder, Encoder};
+use crate::context::{Context, Receiver, Sender};
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Opens a connection, returning its client end and the halves of its
+/// server end.
+pub(crate) async fn connect() -> (WsPeer, Sender<Ws>, Receiver<Ws>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let (tx, rx) = Context::<Ws>::split(server);
+
+    let peer = WsPeer {
+        stream,
+        codec: Ws::client(),
+        buf: BytesMut::new(),
+    };
+
+    (peer, tx, rx)
+}
+
+/// The client end of a loopback connection.
+pub(crate) struct WsPeer {
+    stream: TcpStream,
+    codec: Ws,
+    buf: BytesMut,
+}
+
+impl WsPeer {
+    /// Writes frames, masking them as a client must.
+    pub(crate) async fn send(&mut self, frames: impl IntoIterator<Item = WsFrame>) {
+        let mut buf = BytesMut::new();
+        for mut frame in frames {
+            frame.masked = true;
+            Ws::new().encode(frame, &mut buf).unwrap();
+        }
+
+        self.stream.write_all(&buf).await.unwrap();
+    }
+
+    /// Reads the next frame, `None` once the server closed the connection.
+    pub(crate) async fn next(&mut self) -> Option<WsFrame> {
+        loop {
+            if let Some(frame) = self.codec.decode(&mut self.buf).unwrap() {
+                return Some(frame);
+            }
+
+            if self.stream.read_buf(&mut self.buf).await.unwrap() == 0 {
+                assert!(self.buf.is_empty(), "connection closed mid frame");
+                return None;
+            }
+        }
+    }
+
+    /// Reads `n` frames, returning their opcodes and payloads.
+    pub(crate) async fn read_frames(&mut self, n: usize) -> Vec<(Opcode, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while frames.len() < n {
+            let frame = self.next().await.expect("connection closed");
+            frames.push((*frame.opcode(), frame.data().to_vec()));
+        }
+        frames
+    }
+
+    /// Reads frames until the server closes the connection.
+    pub(crate) async fn read_to_end(&mut self) -> Vec<(Opcode, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next().await {
+            frames.push((*frame.opcode(), frame.data().to_vec()));
+        }
+        frames
+    }
+}
+
+pub(crate) fn text(s: &str) -> (Opcode, Vec<u8>) {
+    (Opcode::TEXT, s.as_bytes().to_vec())
+}
@@ -0,0 +1,202 @@
+use crate::codec::websocket::{CloseCode, Message, Opcode, Ws};
+use crate::context::{Receiver, Sender};
+use crate::routing::Route;
+use async_trait::async_trait;
+use futures_core::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::{self, Future};
+use std::marker::PhantomData;
+use trie_rs::params;
+
+/// What a JSON route does with a message it cannot decode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OnDecodeError {
+    /// Closes the connection, with 1003 for binary messages and 1007 for
+    /// text that is not a valid value.
+    Close,
+    /// Answers with `{"error": "..."}` and waits for the next message.
+    Reply,
+}
+
+/// Websocket route exchanging JSON values, see `json`.
+pub struct Json<F, In, Out> {
+    f: F,
+    on_error: OnDecodeError,
+    _marker: PhantomData<fn(In) -> Out>,
+}
+
+/// Websocket route answering each JSON value with a stream of them, see
+/// `json_stream`.
+pub struct JsonStream<F, In, Out> {
+    f: F,
+    on_error: OnDecodeError,
+    _marker: PhantomData<fn(In) -> Out>,
+}
+
+/// Wraps `f` into a websocket route that decodes every text message into an
+/// `In` and sends back the `Out` it returns, if any.
+pub fn json<F, Fut, In, Out>(f: F) -> Json<F, In, Out>
+where
+    F: Fn(In) -> Fut,
+    Fut: Future<Output = Option<Out>>,
+{
+    Json {
+        f,
+        on_error: OnDecodeError::Close,
+        _marker: PhantomData,
+    }
+}
+
+/// Like `json`, sending every value of the stream `f` returns. The next
+/// message is read once the stream has ended.
+pub fn json_stream<F, S, In, Out>(f: F) -> JsonStream<F, In, Out>
+where
+    F: Fn(In) -> S,
+    S: Stream<Item = Out>,
+{
+    JsonStream {
+        f,
+        on_error: OnDecodeError::Close,
+        _marker: PhantomData,
+    }
+}
+
+impl<F, In, Out> Json<F, In, Out> {
+    /// What to do with messages that do not decode, closes the connection
+    /// by default.
+    pub fn on_error(mut self, on_error: OnDecodeError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+}
+
+impl<F, Fut, In, Out> Json<F, In, Out>
+where
+    F: Fn(In) -> Fut,
+    Fut: Future<Output = Option<Out>>,
+    In: DeserializeOwned,
+    Out: Serialize,
+{
+    pub(crate) async fn run(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+    ) -> std::io::Result<()> {
+        while let Some(value) = recv_json(tx, rx, self.on_error).await? {
+            if let Some(out) = (self.f)(value).await {
+                tx.send(to_message(&out)?).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<F, Fut, In, Out> Route<Ws> for Json<F, In, Out>
+where
+    F: Fn(In) -> Fut + Send + Sync,
+    Fut: Future<Output = Option<Out>> + Send,
+    In: DeserializeOwned + Send,
+    Out: Serialize + Send,
+{
+    async fn handle(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+        _params: &params::Params,
+    ) -> std::io::Result<()> {
+        self.run(tx, rx).await
+    }
+}
+
+impl<F, In, Out> JsonStream<F, In, Out> {
+    /// See `Json::on_error`.
+    pub fn on_error(mut self, on_error: OnDecodeError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+}
+
+impl<F, S, In, Out> JsonStream<F, In, Out>
+where
+    F: Fn(In) -> S,
+    S: Stream<Item = Out>,
+    In: DeserializeOwned,
+    Out: Serialize,
+{
+    pub(crate) async fn run(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+    ) -> std::io::Result<()> {
+        while let Some(value) = recv_json(tx, rx, self.on_error).await? {
+            let mut stream = Box::pin((self.f)(value));
+
+            while let Some(out) = future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+                tx.send(to_message(&out)?).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<F, S, In, Out> Route<Ws> for JsonStream<F, In, Out>
+where
+    F: Fn(In) -> S + Send + Sync,
+    S: Stream<Item = Out> + Send,
+    In: DeserializeOwned + Send,
+    Out: Serialize + Send,
+{
+    async fn handle(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+        _params: &params::Params,
+    ) -> std::io::Result<()> {
+        self.run(tx, rx).await
+    }
+}
+
+/// Waits for the next message that decodes into a `T`. Returns `None` once
+/// the peer closed or the connection was closed over a message that did not
+/// decode, and fails if the connection did.
+async fn recv_json<T: DeserializeOwned>(
+    tx: &mut Sender<Ws>,
+    rx: &mut Receiver<Ws>,
+    on_error: OnDecodeError,
+) -> std::io::Result<Option<T>> {
+    loop {
+        let msg = rx.recv().await?;
+
+        let (code, error) = match msg.opcode() {
+            Opcode::TEXT => match serde_json::from_slice(msg.data()) {
+                Ok(value) => return Ok(Some(value)),
+                Err(e) => (CloseCode::Invalid, e.to_string()),
+            },
+            Opcode::BINARY => (
+                CloseCode::Unsupported,
+                "expected a text message".to_string(),
+            ),
+            Opcode::CLOSE => return Ok(None),
+            _ => continue,
+        };
+
+        match on_error {
+            OnDecodeError::Close => {
+                tx.close(code, &error).await?;
+                return Ok(None);
+            }
+            OnDecodeError::Reply => {
+                tx.send(to_message(&serde_json::json!({ "error": error }))?)
+                    .await?;
+            }
+        }
+    }
+}
+
+fn to_message<T: Serialize>(value: &T) -> std::io::Result<Message> {
+    Ok(Message::text(serde_json::to_string(value)?))
+}
@@ -0,0 +1,103 @@
+use crate::codec::websocket::{Opcode, Ws, WsFrame};
+use crate::context::{Receiver, Sender};
+use crate::loopback::{connect, text, WsPeer};
+use crate::routing::{json, json_stream, OnDecodeError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct Add {
+    a: i32,
+    b: i32,
+}
+
+#[derive(Serialize)]
+struct Sum {
+    sum: i32,
+}
+
+/// A connection on which the client has already sent `frames`.
+async fn connection(frames: Vec<WsFrame>) -> (WsPeer, Sender<Ws>, Receiver<Ws>) {
+    let (mut client, tx, rx) = connect().await;
+    client.send(frames).await;
+    (client, tx, rx)
+}
+
+#[tokio::test]
+async fn json_replies_to_values() {
+    let route = json(|add: Add| async move {
+        match add.a + add.b {
+            0 => None,
+            sum => Some(Sum { sum }),
+        }
+    })
+    .on_error(OnDecodeError::Reply);
+
+    let (mut client, mut tx, mut rx) = connection(vec![
+        WsFrame::builder().masked().text(r#"{"a": 1, "b": 2}"#),
+        WsFrame::builder().masked().text(r#"{"a": 1, "b": -1}"#),
+        WsFrame::builder().masked().text(r#"{"a": 1}"#),
+        WsFrame::builder().masked().text(r#"{"a": 2, "b": 3}"#),
+        WsFrame::builder().masked().close(),
+    ])
+    .await;
+
+    route.run(&mut tx, &mut rx).await.unwrap();
+    drop((tx, rx));
+
+    let frames = client.read_to_end().await;
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[0], text(r#"{"sum":3}"#));
+    assert!(String::from_utf8_lossy(&frames[1].1).starts_with(r#"{"error":"missing field `b`"#));
+    assert_eq!(frames[2], text(r#"{"sum":5}"#));
+}
+
+#[tokio::test]
+async fn json_closes_on_decode_errors() {
+    let route = json(|add: Add| async move { Some(Sum { sum: add.a + add.b }) });
+
+    for (frame, code) in [
+        (WsFrame::builder().masked().text("nope"), 1007u16),
+        (WsFrame::builder().masked().binary(vec![1]), 1003),
+    ] {
+        let (mut client, mut tx, mut rx) = connection(vec![frame]).await;
+        route.run(&mut tx, &mut rx).await.unwrap();
+        assert!(tx.is_closed());
+        drop((tx, rx));
+
+        let frames = client.read_to_end().await;
+        assert_eq!(frames[0].0, Opcode::CLOSE);
+        assert_eq!(&frames[0].1[..2], &code.to_be_bytes());
+    }
+}
+
+#[tokio::test]
+async fn json_fails_with_the_connection() {
+    let route = json(|add: Add| async move { Some(Sum { sum: add.a + add.b }) });
+
+    // the peer goes away without closing
+    let (client, mut tx, mut rx) = connection(Vec::new()).await;
+    drop(client);
+
+    let err = route.run(&mut tx, &mut rx).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn json_streams_values() {
+    let route = json_stream(|n: u32| futures::stream::iter(0..n));
+
+    let (mut client, mut tx, mut rx) = connection(vec![
+        WsFrame::builder().masked().text("3"),
+        WsFrame::builder().masked().text("1"),
+        WsFrame::builder().masked().close(),
+    ])
+    .await;
+
+    route.run(&mut tx, &mut rx).await.unwrap();
+    drop((tx, rx));
+
+    assert_eq!(
+        client.read_to_end().await,
+        [text("0"), text("1"), text("2"), text("0")]
+    );
+}
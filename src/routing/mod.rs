@@ -1,5 +1,12 @@
+#[cfg(feature = "json")]
+mod json;
 mod route;
 mod router;
 
+#[cfg(feature = "json")]
+pub use json::*;
 pub use route::*;
 pub use router::*;
+
+#[cfg(all(test, feature = "json"))]
+mod json_test;
@@ -13,6 +13,18 @@ pub trait Route<Codec> {
         rx: &mut Receiver<Codec>,
         params: &params::Params,
     ) -> std::io::Result<()>;
+
+    /// Called with the upgrade request of a websocket route once the
+    /// handshake has been validated, e.g. to check cookies, `Origin` or a
+    /// query token. Returning a response, e.g. 401 or 403, rejects the
+    /// upgrade and closes the connection.
+    async fn upgrade(
+        &self,
+        _req: &Request<Body>,
+        _params: &params::Params,
+    ) -> Option<Response<Body>> {
+        None
+    }
 }
 
 #[async_trait]
@@ -22,6 +34,17 @@ pub trait HttpRoute: Send + Sync {
         req: &Request<Body>,
         params: &params::Params,
     ) -> std::io::Result<Response<Body>>;
+
+    /// Called for requests sent with `Expect: 100-continue` before their body
+    /// is read. Returning a response, e.g. 413 or 401, rejects the request
+    /// and closes the connection, otherwise `100 Continue` is sent.
+    async fn expect_continue(
+        &self,
+        _req: &Request<()>,
+        _params: &params::Params,
+    ) -> Option<Response<Body>> {
+        None
+    }
 }
 
 pub struct Wrap {
@@ -1,8 +1,12 @@
-use crate::codec::websocket::Ws;
-use crate::context::Body;
+use crate::codec::{http::has_token, websocket::Ws};
+use crate::context::{Body, ClientInfo, TrustedProxies};
 use crate::routing::route::{HttpRoute, Route};
+use crate::ws::WsConfig;
 use async_trait::async_trait;
+use http::header::{HeaderValue, UPGRADE};
 use http::{Method, Request, Response};
+use std::borrow::Cow;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use trie_rs::params::Params;
 use trie_rs::path::PathTrie;
@@ -14,11 +18,15 @@ pub struct Router {
     delete_routes: PathTrie<Arc<Endpoint>>,
     ws: PathTrie<Arc<Endpoint>>,
     not_found: Arc<Endpoint>,
+    proxies: TrustedProxies,
+    server: Option<HeaderValue>,
+    ws_config: WsConfig,
+    max_body_size: usize,
 }
 
 pub(crate) enum Endpoint {
     Http(Box<dyn HttpRoute + Send + Sync>),
-    Ws(Box<dyn Route<Ws> + Send + Sync>),
+    Ws(Box<dyn Route<Ws> + Send + Sync>, Option<WsConfig>),
 }
 
 impl Router {
@@ -30,6 +38,10 @@ impl Router {
             delete_routes: PathTrie::new(),
             ws: PathTrie::new(),
             not_found: Arc::new(Endpoint::Http(Box::new(NotFound {}))),
+            proxies: TrustedProxies::new(),
+            server: None,
+            ws_config: WsConfig::new(),
+            max_body_size: 2 << 20,
         }
     }
 
@@ -74,10 +86,41 @@ impl Router {
         R: Route<Ws> + Send + Sync + 'static,
     {
         self.ws
-            .insert(path, Arc::new(Endpoint::Ws(Box::new(route))));
+            .insert(path, Arc::new(Endpoint::Ws(Box::new(route), None)));
         self
     }
 
+    /// Registers a websocket route with its own settings instead of the
+    /// router wide `ws_config`. The origins allowed router wide still apply
+    /// to the route unless its settings allow origins of their own.
+    pub fn ws_with<R>(mut self, path: &str, route: R, config: WsConfig) -> Self
+    where
+        R: Route<Ws> + Send + Sync + 'static,
+    {
+        self.ws
+            .insert(path, Arc::new(Endpoint::Ws(Box::new(route), Some(config))));
+        self
+    }
+
+    /// Sets the settings used by websocket routes registered without their
+    /// own.
+    pub fn ws_config(mut self, config: WsConfig) -> Self {
+        self.ws_config = config;
+        self
+    }
+
+    /// The settings of a websocket route, registered with `config` or
+    /// without settings of its own.
+    pub(crate) fn route_ws_config<'a>(&'a self, config: Option<&'a WsConfig>) -> Cow<'a, WsConfig> {
+        match config {
+            Some(config) if config.inherits_origins(&self.ws_config) => {
+                Cow::Owned(config.clone().with_origins_of(&self.ws_config))
+            }
+            Some(config) => Cow::Borrowed(config),
+            None => Cow::Borrowed(&self.ws_config),
+        }
+    }
+
     pub fn not_found<R>(mut self, route: R) -> Self
     where
         R: HttpRoute + Send + Sync + 'static,
@@ -86,21 +129,53 @@ impl Router {
         self
     }
 
+    /// Sets the proxies whose forwarding headers are used to resolve the
+    /// `ClientInfo` of each request. No proxy is trusted by default.
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    /// Adds a `Server` header to responses that do not set one, e.g.
+    /// `HeaderValue::from_static("supercruise")`.
+    pub fn server(mut self, name: HeaderValue) -> Self {
+        self.server = Some(name);
+        self
+    }
+
+    /// Sets the largest request body accepted, larger ones are answered
+    /// with `413 Payload Too Large` and the connection is closed. 2 MiB by
+    /// default.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    pub(crate) fn body_limit(&self) -> usize {
+        self.max_body_size
+    }
+
+    pub(crate) fn server_header(&self) -> Option<&HeaderValue> {
+        self.server.as_ref()
+    }
+
+    pub(crate) fn client_info(&self, req: &Request<Body>, peer: SocketAddr) -> ClientInfo {
+        self.proxies.resolve(peer, req.headers())
+    }
+
     #[inline]
-    pub(crate) fn route<'a, 'b>(
+    pub(crate) fn route<'a, 'b, B>(
         &'a self,
-        req: &'b Request<Body>,
+        req: &'b Request<B>,
     ) -> (Arc<Endpoint>, Params<'a, 'b>) {
         let path = req.uri().path();
         let method = req.method();
 
-        if let Some(value) = req.headers().get("Upgrade") {
-            if value == "websocket" {
-                return match self.ws.get(path) {
-                    Some((r, params)) => (r.clone(), params),
-                    None => (self.not_found.clone(), Params::new()),
-                };
-            }
+        if has_token(req.headers(), UPGRADE, "websocket") {
+            return match self.ws.get(path) {
+                Some((r, params)) => (r.clone(), params),
+                None => (self.not_found.clone(), Params::new()),
+            };
         }
 
         match method {
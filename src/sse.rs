@@ -0,0 +1,234 @@
+use crate::codec::{http::Http, Encoder};
+use crate::context::Body;
+use bytes::BytesMut;
+use futures_core::Stream;
+use http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use std::future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+type Events = Pin<Box<dyn Stream<Item = Event> + Send>>;
+
+/// A `text/event-stream` response, streaming events to the client until the
+/// stream ends or the client disconnects.
+///
+/// Routes return it like any other response, converted with `into`. The
+/// connection is closed once the stream ends, and the stream is dropped as
+/// soon as the client goes away, so producers notice through whatever feeds
+/// it, e.g. the `Sender` of `Sse::channel` failing.
+pub struct Sse {
+    events: Events,
+    keep_alive: Duration,
+}
+
+impl Sse {
+    pub fn new<S>(events: S) -> Self
+    where
+        S: Stream<Item = Event> + Send + 'static,
+    {
+        Self {
+            events: Box::pin(events),
+            keep_alive: Duration::from_secs(15),
+        }
+    }
+
+    /// A response streaming the events sent on the returned channel, ending
+    /// once every sender has been dropped.
+    pub fn channel(capacity: usize) -> (mpsc::Sender<Event>, Self) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (tx, Self::new(Channel(rx)))
+    }
+
+    /// Sends a comment after `interval` without any event, so that proxies
+    /// do not time out an idle stream. Defaults to 15 seconds, zero disables
+    /// it.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+
+    /// The ID of the last event a reconnecting client received, from which
+    /// the stream should resume.
+    pub fn last_event_id<T>(req: &Request<T>) -> Option<&str> {
+        req.headers()
+            .get("last-event-id")
+            .and_then(|id| id.to_str().ok())
+    }
+}
+
+impl From<Sse> for Response<Body> {
+    fn from(sse: Sse) -> Self {
+        let mut resp = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"))
+            .header(CACHE_CONTROL, HeaderValue::from_static("no-cache"))
+            .body(Body::empty())
+            .unwrap();
+
+        resp.extensions_mut().insert(Streaming(Mutex::new(sse)));
+        resp
+    }
+}
+
+/// Carries an `Sse` to the connection loop in the extensions of its
+/// response. The mutex only makes the stream `Sync`, as extensions must be.
+struct Streaming(Mutex<Sse>);
+
+/// Takes the event stream out of a response built from an `Sse`.
+pub(crate) fn take(resp: &mut Response<Body>) -> Option<Sse> {
+    let streaming = resp.extensions_mut().remove::<Streaming>()?;
+    Some(streaming.0.into_inner().unwrap())
+}
+
+/// Writes the head of the response and then its events, returning once the
+/// stream has ended or the client has disconnected. The caller closes the
+/// connection afterwards.
+pub(crate) async fn stream(
+    stream: &mut TcpStream,
+    resp: Response<Body>,
+    mut sse: Sse,
+) -> std::io::Result<()> {
+    let (parts, _) = resp.into_parts();
+    let mut buf = BytesMut::new();
+    Http::<Body>::new()
+        .encode(Response::from_parts(parts, ()), &mut buf)
+        .unwrap();
+    stream.write_all(&buf).await?;
+
+    let mut input = BytesMut::new();
+
+    loop {
+        buf.clear();
+        let keep_alive = tokio::time::sleep(sse.keep_alive);
+
+        tokio::select! {
+            event = future::poll_fn(|cx| sse.events.as_mut().poll_next(cx)) => match event {
+                Some(event) => event.encode(&mut buf),
+                None => break,
+            },
+            _ = keep_alive, if !sse.keep_alive.is_zero() => buf.extend_from_slice(b":\n\n"),
+            // the client has nothing to send, only its disconnecting matters
+            read = stream.read_buf(&mut input) => match read {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(_) => {
+                    input.clear();
+                    continue;
+                }
+            },
+        }
+
+        if stream.write_all(&buf).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    let _ = stream.shutdown().await;
+    Ok(())
+}
+
+struct Channel(mpsc::Receiver<Event>);
+
+impl Stream for Channel {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+/// A server-sent event. Fields left unset are not sent, an event without
+/// data is not dispatched by browsers but still updates the last event ID
+/// and retry delay.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The payload, sent as one `data` field per line.
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// The event type, `message` when unset. Line breaks are removed.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// The ID the client sends back in `Last-Event-ID` when it reconnects.
+    /// Line breaks and NUL characters are removed.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        let mut id = single_line(id.into());
+        id.retain(|c| c != '\0');
+        self.id = Some(id);
+        self
+    }
+
+    /// How long the client waits before reconnecting after losing the
+    /// connection.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// A comment, ignored by clients. Line breaks are removed.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(single_line(comment.into()));
+        self
+    }
+
+    pub(crate) fn encode(&self, dest: &mut BytesMut) {
+        let fields = [
+            ("", &self.comment),
+            ("event", &self.event),
+            ("id", &self.id),
+        ];
+
+        for (name, value) in fields {
+            if let Some(value) = value {
+                field(dest, name, value);
+            }
+        }
+
+        if let Some(retry) = self.retry {
+            field(dest, "retry", &retry.as_millis().to_string());
+        }
+
+        if let Some(data) = &self.data {
+            // any line ending ends a field, split on all of them
+            for line in data.replace("\r\n", "\n").split(&['\r', '\n'][..]) {
+                field(dest, "data", line);
+            }
+        }
+
+        dest.extend_from_slice(b"\n");
+    }
+}
+
+fn field(dest: &mut BytesMut, name: &str, value: &str) {
+    dest.extend_from_slice(name.as_bytes());
+    dest.extend_from_slice(b": ");
+    dest.extend_from_slice(value.as_bytes());
+    dest.extend_from_slice(b"\n");
+}
+
+fn single_line(mut s: String) -> String {
+    s.retain(|c| c != '\r' && c != '\n');
+    s
+}
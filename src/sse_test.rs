@@ -0,0 +1,120 @@
+use crate::context::Body;
+use crate::routing::{wrap, FnOutput, HttpRoute, Router};
+use crate::sse::{Event, Sse};
+use crate::testing::TestServer;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use http::{Request, Response, StatusCode};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use trie_rs::params::Params;
+
+#[test]
+fn sse_event_encoding() {
+    let event = Event::new()
+        .comment("hello\nthere")
+        .event("update")
+        .id("7\r\n")
+        .retry(Duration::from_secs(3))
+        .data("a\nb\r\nc\rd");
+
+    let mut buf = BytesMut::new();
+    event.encode(&mut buf);
+    assert_eq!(
+        &buf[..],
+        &b": hellothere\nevent: update\nid: 7\nretry: 3000\ndata: a\ndata: b\ndata: c\ndata: d\n\n"
+            [..]
+    );
+
+    let mut buf = BytesMut::new();
+    Event::new().data("").encode(&mut buf);
+    assert_eq!(&buf[..], b"data: \n\n");
+}
+
+/// Counts to 3, resuming after the last event the client received.
+fn count(req: &Request<Body>, _params: &Params) -> FnOutput<Response<Body>> {
+    let start = Sse::last_event_id(req)
+        .and_then(|id| id.parse::<u32>().ok())
+        .map_or(0, |id| id + 1);
+
+    let events = (start..3).map(|i| Event::new().id(i.to_string()).data(i.to_string()));
+    let sse = Sse::new(futures::stream::iter(events));
+
+    Box::pin(async move { Ok(sse.into()) })
+}
+
+#[tokio::test]
+async fn sse_streams_events() {
+    let server = TestServer::start(Router::new().get("/count", wrap(count)))
+        .await
+        .unwrap();
+    let client = server.client();
+
+    client
+        .get("/count")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_content_type("text/event-stream")
+        .assert_header("cache-control", "no-cache")
+        .assert_header("connection", "close")
+        .assert_text("id: 0\ndata: 0\n\nid: 1\ndata: 1\n\nid: 2\ndata: 2\n\n");
+
+    client
+        .get("/count")
+        .header("last-event-id", "1")
+        .send()
+        .await
+        .assert_text("id: 2\ndata: 2\n\n");
+}
+
+/// Hands the sending half of every stream it opens to the test.
+struct Feed(mpsc::UnboundedSender<mpsc::Sender<Event>>);
+
+#[async_trait]
+impl HttpRoute for Feed {
+    async fn handle(
+        &self,
+        _req: &Request<Body>,
+        _params: &Params,
+    ) -> std::io::Result<Response<Body>> {
+        let (tx, sse) = Sse::channel(8);
+        self.0.send(tx).unwrap();
+        Ok(sse.keep_alive(Duration::from_millis(50)).into())
+    }
+}
+
+async fn read_until(stream: &mut TcpStream, buf: &mut BytesMut, needle: &[u8]) {
+    while !buf.windows(needle.len()).any(|w| w == needle) {
+        assert_ne!(stream.read_buf(buf).await.unwrap(), 0);
+    }
+}
+
+#[tokio::test]
+async fn sse_keep_alive_and_disconnect() {
+    let (feeds, mut opened) = mpsc::unbounded_channel();
+    let server = TestServer::start(Router::new().get("/feed", Feed(feeds)))
+        .await
+        .unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream
+        .write_all(b"GET /feed HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    let tx = opened.recv().await.unwrap();
+    tx.send(Event::new().data("hi")).await.unwrap();
+
+    let mut buf = BytesMut::new();
+    read_until(&mut stream, &mut buf, b"\r\n\r\ndata: hi\n\n").await;
+    read_until(&mut stream, &mut buf, b"data: hi\n\n:\n\n").await;
+
+    // the stream is dropped once the client goes away
+    drop(stream);
+    tokio::time::timeout(Duration::from_secs(1), tx.closed())
+        .await
+        .unwrap();
+}
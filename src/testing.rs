@@ -0,0 +1,291 @@
+//! Running a `Router` inside a test, behind the `testing` feature, which is
+//! meant to be enabled from `dev-dependencies`.
+//!
+//! `TestServer` serves the router on an ephemeral port of the loopback
+//! interface, through the same connection loop as `serve`, on the runtime of
+//! the test:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn hello() {
+//!     let server = TestServer::start(router()).await.unwrap();
+//!     let client = server.client();
+//!
+//!     client
+//!         .get("/hello")
+//!         .send()
+//!         .await
+//!         .assert_status(StatusCode::OK)
+//!         .assert_text("hello");
+//! }
+//! ```
+
+use crate::client::{Client, WsClient, WsClientError};
+use crate::codec::websocket::Ws;
+use crate::context::{Body, Receiver, Sender};
+use crate::routing::Router;
+use crate::worker;
+use bytes::BytesMut;
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use http::{Method, Request, Response, StatusCode};
+#[cfg(feature = "json")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// A router served on `127.0.0.1` until dropped.
+///
+/// Connections still open when the server is dropped keep being served
+/// until they close.
+pub struct TestServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Binds an ephemeral port and starts accepting connections on it. Must
+    /// be called within a tokio runtime.
+    ///
+    /// The router is leaked, as it is by `serve`, since connections borrow
+    /// it for as long as they last. Every server started leaks its router,
+    /// which is fine over the runs of a test binary.
+    pub async fn start(router: Router) -> std::io::Result<Self> {
+        let incoming = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = incoming.local_addr()?;
+        let router: &'static Router = Box::leak(Box::new(router));
+
+        let task = tokio::spawn(async move {
+            if let Err(e) = worker::accept(incoming, router).await {
+                log::error!("test server stopped: {}", e);
+            }
+        });
+
+        Ok(Self { addr, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The `http://` url of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// A client for this server, with its own connection pool.
+    pub fn client(&self) -> TestClient {
+        TestClient {
+            addr: self.addr,
+            client: Client::new(),
+            ws: WsClient::new(),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Sends requests to a `TestServer` by path and opens websocket sessions on
+/// it.
+#[derive(Clone)]
+pub struct TestClient {
+    addr: SocketAddr,
+    client: Client,
+    ws: WsClient,
+}
+
+impl TestClient {
+    /// Replaces the HTTP client, e.g. to change its timeouts.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Replaces the websocket client, e.g. to offer subprotocols or send
+    /// extra headers with the upgrade request.
+    pub fn with_ws_client(mut self, ws: WsClient) -> Self {
+        self.ws = ws;
+        self
+    }
+
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> TestRequest {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn delete(&self, path: &str) -> TestRequest {
+        self.request(Method::DELETE, path)
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> TestRequest {
+        let uri = format!("http://{}{}", self.addr, path);
+
+        TestRequest {
+            client: self.client.clone(),
+            req: Request::builder().method(method).uri(uri),
+            body: Body::empty(),
+        }
+    }
+
+    /// Opens a websocket session on `path`.
+    pub async fn ws(&self, path: &str) -> Result<(Sender<Ws>, Receiver<Ws>), WsClientError> {
+        self.ws.open(&format!("ws://{}{}", self.addr, path)).await
+    }
+}
+
+/// A request being built, see `TestClient::request`.
+pub struct TestRequest {
+    client: Client,
+    req: http::request::Builder,
+    body: Body,
+}
+
+impl TestRequest {
+    /// Adds a header. Panics if it is not a valid one.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        let value = HeaderValue::from_str(value).expect("invalid header value");
+        self.req = self.req.header(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets the body to `value` serialized as JSON, with the matching
+    /// `Content-Type`.
+    #[cfg(feature = "json")]
+    pub fn json<T: Serialize>(self, value: &T) -> Self {
+        let body = serde_json::to_vec(value).expect("failed to serialize body");
+        self.header("content-type", "application/json").body(body)
+    }
+
+    /// Sends the request and reads the whole response. Panics if the
+    /// request fails, the server answering with an error status is not a
+    /// failure.
+    pub async fn send(self) -> TestResponse {
+        let req = self.req.body(self.body).expect("invalid request");
+
+        match self.client.send(req).await {
+            Ok(resp) => TestResponse::new(resp),
+            Err(e) => panic!("request failed: {}", e),
+        }
+    }
+}
+
+/// A response read by a `TestRequest`, with assertions that can be chained.
+#[derive(Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: BytesMut,
+}
+
+impl TestResponse {
+    fn new(resp: Response<Body>) -> Self {
+        let mut body = BytesMut::new();
+        resp.body().bytes(&mut body);
+
+        let (parts, _) = resp.into_parts();
+
+        Self {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The value of a header, if it is present and valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body as text. Panics if it is not valid UTF-8.
+    pub fn text(&self) -> &str {
+        std::str::from_utf8(&self.body).expect("body is not valid UTF-8")
+    }
+
+    /// The body decoded from JSON. Panics if it does not decode into a `T`.
+    #[cfg(feature = "json")]
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        match serde_json::from_slice(&self.body) {
+            Ok(value) => value,
+            Err(e) => panic!("body is not valid JSON: {}: {:?}", e, self.text()),
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_status(self, status: StatusCode) -> Self {
+        assert_eq!(
+            self.status, status,
+            "unexpected status, body: {:?}",
+            self.body
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_header(self, name: &str, value: &str) -> Self {
+        assert_eq!(self.header(name), Some(value), "unexpected {} header", name);
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_text(self, text: &str) -> Self {
+        assert_eq!(self.text(), text, "unexpected body");
+        self
+    }
+
+    /// Asserts that the body is JSON equal to `value`.
+    #[cfg(feature = "json")]
+    #[track_caller]
+    pub fn assert_json<T: Serialize>(self, value: &T) -> Self {
+        let expected = serde_json::to_value(value).expect("failed to serialize value");
+        assert_eq!(
+            self.json::<serde_json::Value>(),
+            expected,
+            "unexpected body"
+        );
+        self
+    }
+
+    /// Asserts that `Content-Type` is `content_type`, ignoring parameters
+    /// such as the charset.
+    #[track_caller]
+    pub fn assert_content_type(self, content_type: &str) -> Self {
+        let actual = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(str::trim);
+
+        assert_eq!(actual, Some(content_type), "unexpected content type");
+        self
+    }
+}
@@ -0,0 +1,341 @@
+use crate::codec::websocket::{Message, Opcode, Ws};
+use crate::context::{Body, ConnInfo, Receiver, Sender};
+use crate::routing::{json, wrap, FnOutput, HttpRoute, Route, Router};
+use crate::testing::TestServer;
+use http::{Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use trie_rs::params::Params;
+
+#[derive(Serialize, Deserialize)]
+struct Sum {
+    a: i64,
+    b: i64,
+}
+
+fn echo(req: &Request<Body>, _params: &Params) -> FnOutput<Response<Body>> {
+    let mut body = bytes::BytesMut::new();
+    req.body().bytes(&mut body);
+
+    let content_type = req
+        .headers()
+        .get("content-type")
+        .cloned()
+        .unwrap_or_else(|| http::HeaderValue::from_static("text/plain"));
+
+    Box::pin(async move {
+        let resp = Response::builder()
+            .header("content-type", content_type)
+            .body(body.to_vec().into())
+            .unwrap();
+
+        Ok(resp)
+    })
+}
+
+fn router() -> Router {
+    Router::new()
+        .server(http::HeaderValue::from_static("test"))
+        .post("/echo", wrap(echo))
+        .ws("/sum", json(|sum: Sum| async move { Some(sum.a + sum.b) }))
+}
+
+#[tokio::test]
+async fn test_server_serves_http() {
+    let server = TestServer::start(router()).await.unwrap();
+    let client = server.client();
+
+    client
+        .post("/echo")
+        .body("hello")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_header("server", "test")
+        .assert_content_type("text/plain")
+        .assert_text("hello");
+
+    let resp = client
+        .post("/echo")
+        .json(&Sum { a: 1, b: 2 })
+        .send()
+        .await
+        .assert_content_type("application/json")
+        .assert_json(&serde_json::json!({ "a": 1, "b": 2 }));
+    assert_eq!(resp.json::<Sum>().b, 2);
+
+    client
+        .get("/missing")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_server_serves_websockets() {
+    let server = TestServer::start(router()).await.unwrap();
+    let (mut tx, mut rx) = server.client().ws("/sum").await.unwrap();
+
+    tx.send(Message::text(r#"{"a": 2, "b": 3}"#)).await.unwrap();
+    let msg = rx.recv().await.unwrap();
+    assert_eq!(*msg.opcode(), Opcode::TEXT);
+    assert_eq!(msg.data(), b"5");
+
+    tx.send(Message::text("nope")).await.unwrap();
+    let msg = rx.recv().await.unwrap();
+    assert_eq!(*msg.opcode(), Opcode::CLOSE);
+}
+
+/// Sends raw request bytes on a new connection and reads until the server
+/// closes it.
+async fn raw(server: &TestServer, req: &[u8]) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+    stream.write_all(req).await.unwrap();
+
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    resp
+}
+
+#[tokio::test]
+async fn test_server_handles_transfer_encodings() {
+    let server = TestServer::start(router()).await.unwrap();
+
+    let resp = raw(
+        &server,
+        b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+          2\r\nhe\r\n3\r\nllo\r\n0\r\n\r\n",
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(resp.ends_with("\r\n\r\nhello"));
+
+    let resp = raw(
+        &server,
+        b"POST /echo HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    assert!(resp.contains("connection: close\r\n"));
+}
+
+#[tokio::test]
+async fn test_server_limits_request_bodies() {
+    let server = TestServer::start(router().max_body_size(4)).await.unwrap();
+
+    // answered without waiting for the body
+    let resp = raw(&server, b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\n").await;
+    assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    assert!(resp.contains("connection: close\r\n"));
+}
+
+#[tokio::test]
+async fn test_server_refuses_ambiguous_framing() {
+    let server = TestServer::start(router()).await.unwrap();
+
+    let resp = raw(
+        &server,
+        b"POST /echo HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n\
+          0\r\n\r\n",
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(resp.contains("connection: close\r\n"));
+}
+
+struct Guarded;
+
+#[async_trait::async_trait]
+impl HttpRoute for Guarded {
+    async fn handle(
+        &self,
+        _req: &Request<Body>,
+        _params: &Params,
+    ) -> std::io::Result<Response<Body>> {
+        Ok(Response::new("handled".into()))
+    }
+
+    async fn expect_continue(&self, req: &Request<()>, _params: &Params) -> Option<Response<Body>> {
+        match req.headers().contains_key("authorization") {
+            true => None,
+            false => Some(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(().into())
+                    .unwrap(),
+            ),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_server_handles_expect_continue() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::start(router().post("/guarded", Guarded))
+        .await
+        .unwrap();
+
+    // the interim response comes before any of the body is sent
+    let mut stream = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+    stream
+        .write_all(
+            b"POST /echo HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut interim = Vec::new();
+    while !interim.ends_with(b"\r\n\r\n") {
+        assert_ne!(stream.read_buf(&mut interim).await.unwrap(), 0);
+    }
+    assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+    stream.write_all(b"hello").await.unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(resp.ends_with("\r\n\r\nhello"));
+
+    // a rejected request is answered without its body ever being sent
+    let resp = raw(
+        &server,
+        b"POST /guarded HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    assert!(resp.contains("connection: close\r\n"));
+    assert!(!resp.contains("100 Continue"));
+
+    // no interim response is needed once the body is already there
+    let resp = raw(
+        &server,
+        b"POST /guarded HTTP/1.1\r\nExpect: 100-continue\r\nAuthorization: x\r\n\
+          Content-Length: 5\r\nConnection: close\r\n\r\nhello",
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(resp.ends_with("\r\n\r\nhandled"));
+}
+
+#[tokio::test]
+async fn test_server_keeps_connections_alive() {
+    let server = TestServer::start(router()).await.unwrap();
+
+    // HTTP/1.0 closes after every response unless asked not to
+    let resp = raw(
+        &server,
+        b"POST /echo HTTP/1.0\r\nContent-Length: 2\r\n\r\nhi",
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(resp.contains("connection: close\r\n"));
+    assert!(resp.ends_with("\r\n\r\nhi"));
+
+    // the second request is only answered if the connection stayed open
+    let resp = raw(
+        &server,
+        b"POST /echo HTTP/1.0\r\nConnection: keep-alive\r\nContent-Length: 1\r\n\r\na\
+          POST /echo HTTP/1.0\r\nContent-Length: 1\r\n\r\nb",
+    )
+    .await;
+    let (first, second) = resp.split_at(resp.find("\r\n\r\na").unwrap() + 5);
+    assert!(first.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(first.contains("connection: keep-alive\r\n"));
+    assert!(second.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(second.contains("connection: close\r\n"));
+    assert!(second.ends_with("\r\n\r\nb"));
+
+    // HTTP/1.1 stays open unless asked to close
+    let resp = raw(
+        &server,
+        b"POST /echo HTTP/1.1\r\nContent-Length: 1\r\n\r\na\
+          POST /echo HTTP/1.1\r\nConnection: close\r\nContent-Length: 1\r\n\r\nb",
+    )
+    .await;
+    let (first, second) = resp.split_at(resp.find("\r\n\r\na").unwrap() + 5);
+    assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(!first.contains("connection:"));
+    assert!(second.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(second.contains("connection: close\r\n"));
+    assert!(second.ends_with("\r\n\r\nb"));
+}
+
+fn describe(info: &ConnInfo) -> String {
+    format!(
+        "{} {} {} {}",
+        info.id(),
+        info.requests(),
+        info.peer_addr(),
+        info.local_addr()
+    )
+}
+
+fn conn(req: &Request<Body>, _params: &Params) -> FnOutput<Response<Body>> {
+    let info = describe(req.extensions().get::<ConnInfo>().unwrap());
+    Box::pin(async move { Ok(Response::new(info.into())) })
+}
+
+struct WsConn;
+
+#[async_trait::async_trait]
+impl Route<Ws> for WsConn {
+    async fn handle(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+        _params: &Params,
+    ) -> std::io::Result<()> {
+        let info = describe(rx.extensions().get::<ConnInfo>().unwrap());
+        tx.send(Message::text(info)).await
+    }
+}
+
+#[tokio::test]
+async fn test_server_passes_conn_info() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::start(router().get("/conn", wrap(conn)).ws("/conn", WsConn))
+        .await
+        .unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+    let peer = stream.local_addr().unwrap();
+    stream
+        .write_all(
+            b"GET /conn HTTP/1.1\r\n\r\n\
+              GET /conn HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+
+    // both requests share the connection, which counts them
+    let infos: Vec<Vec<&str>> = resp
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .map(|resp| {
+            let (_, body) = resp.split_once("\r\n\r\n").unwrap();
+            body.split(' ').collect()
+        })
+        .collect();
+
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[0][0], infos[1][0]);
+    assert_eq!(infos[0][1], "1");
+    assert_eq!(infos[1][1], "2");
+    assert_eq!(infos[0][2], peer.to_string());
+    assert_eq!(infos[0][3], server.addr().to_string());
+
+    // a websocket handler sees the upgrade request's connection
+    let (_tx, mut rx) = server.client().ws("/conn").await.unwrap();
+    let msg = rx.recv().await.unwrap();
+    let info: Vec<&str> = msg.as_str().unwrap().split(' ').collect();
+    assert_ne!(info[0], infos[0][0]);
+    assert_eq!(info[1], "1");
+    assert_eq!(info[3], server.addr().to_string());
+}
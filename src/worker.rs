@@ -1,15 +1,17 @@
 use crate::codec::{
-    http::Http,
-    websocket::{Ws, WsFrame},
+    http::{has_token, Http},
+    websocket::Ws,
     Decoder,
 };
-use crate::context::{Body, Context};
+use crate::context::{Body, ConnInfo, Context};
 use crate::routing::{Endpoint, Router};
-use crate::ws::{ErrorEnum, WsUpgrader};
+use crate::sse;
+use crate::ws::{self, ErrorEnum, Handshake, WsUpgrader};
 use bytes::BytesMut;
-use http::{Request, Response, StatusCode};
+use http::header::{HeaderValue, CONNECTION, EXPECT, SERVER};
+use http::{Request, Response, StatusCode, Version};
 use std::net::SocketAddr;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
 pub fn serve<F>(addr: &'static str, router_fn: F)
@@ -68,99 +70,291 @@ async fn worker(
     let incoming = TcpListener::from_std(socket.into()).unwrap();
     let router: &'static Router = Box::leak(Box::new(router));
 
+    accept(incoming, router).await?;
+    Ok(())
+}
+
+/// Serves every connection accepted on `incoming` with `router`, each on its
+/// own task.
+pub(crate) async fn accept(incoming: TcpListener, router: &'static Router) -> std::io::Result<()> {
     loop {
-        let (mut socket, addr) = incoming.accept().await?;
+        let (socket, addr) = incoming.accept().await?;
         let instance = router.clone();
+        let info = ConnInfo::new(addr, socket.local_addr()?);
 
-        log::debug!("new connection {:?}", addr);
+        log::debug!("new connection {:?} id {}", addr, info.id());
 
         tokio::spawn(async move {
-            if let Err(e) = process(&instance, &mut socket).await {
-                match e {
-                    ErrorEnum::IO(ref err) => match err.kind() {
-                        std::io::ErrorKind::ConnectionAborted
-                        | std::io::ErrorKind::ConnectionReset
-                        | std::io::ErrorKind::ConnectionRefused => {}
-                        e => {
-                            let resp = Response::builder()
-                                .status(StatusCode::REQUEST_TIMEOUT)
-                                .body(().into())
-                                .unwrap();
-
-                            let mut ctx = Context::<Http<_>>::from(&mut socket);
-                            let _ = ctx.send(resp).await;
-                            log::debug!("error {}", e);
-                        }
-                    },
-                    _ => {}
-                }
+            if let Err(e) = process(&instance, socket, info).await {
+                log::debug!("error {}", e);
             }
         });
     }
 }
 
-async fn process(router: &'static Router, stream: &mut TcpStream) -> Result<(), ErrorEnum> {
+/// Serves HTTP requests on a connection until it closes, or hands it over to
+/// a websocket route once upgraded.
+async fn process(
+    router: &'static Router,
+    mut stream: TcpStream,
+    mut info: ConnInfo,
+) -> Result<(), ErrorEnum> {
     let mut bytes = BytesMut::with_capacity(8192);
     stream.set_nodelay(true).unwrap();
 
+    match http(router, &mut stream, &mut bytes, &mut info).await {
+        Ok(Some((req, handshake))) => websocket(router, stream, req, handshake, bytes).await,
+        Ok(None) => Ok(()),
+        Err(ErrorEnum::IO(err)) => match err.kind() {
+            std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionRefused => Ok(()),
+            _ => {
+                let resp = Response::builder()
+                    .status(StatusCode::REQUEST_TIMEOUT)
+                    .body(().into())
+                    .unwrap();
+
+                let mut ctx = Context::<Http<_>>::from(&mut stream);
+                let _ = ctx.send(resp).await;
+                Err(ErrorEnum::IO(err))
+            }
+        },
+    }
+}
+
+/// Returns the request and what was negotiated once the connection has been
+/// upgraded to a websocket.
+async fn http(
+    router: &'static Router,
+    stream: &mut TcpStream,
+    bytes: &mut BytesMut,
+    info: &mut ConnInfo,
+) -> Result<Option<(Request<Body>, Handshake)>, ErrorEnum> {
     loop {
         let mut codec = Http::new();
-        if stream.read_buf(&mut bytes).await? == 0 {
-            return Ok(());
-        }
+        codec.set_max_body_size(router.body_limit());
+        let mut expecting = true;
+
+        let mut req: Request<Body> = loop {
+            match codec.decode(bytes) {
+                Ok(Some(req)) => break req,
+                Ok(None) => {}
+                Err(e) => {
+                    log::error!("failed to parse request bytes: {}", e);
 
-        let mut res = codec.decode(&mut bytes);
+                    let mut resp = Response::builder()
+                        .status(e.status())
+                        .header(CONNECTION, "close")
+                        .body(().into())
+                        .unwrap();
+                    set_server(router, &mut resp);
 
-        while let Ok(None) = res {
-            if stream.read_buf(&mut bytes).await? == 0 {
-                return Ok(());
+                    let mut context: Context<Http<_>> = Context::from(stream);
+                    context.send(resp).await?;
+                    return Ok(None);
+                }
             }
 
-            res = codec.decode(&mut bytes);
-        }
+            if let (true, Some(head)) = (expecting, codec.pending()) {
+                expecting = false;
 
-        let req: Request<Body> = match res {
-            Ok(Some(req)) => req,
-            _ => {
-                log::error!("failed to parse request bytes");
-                return Ok(());
+                match expect_continue(router, head).await {
+                    Ok(true) => stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?,
+                    Ok(false) => {}
+                    Err(mut resp) => {
+                        *resp.version_mut() = head.version();
+                        resp.headers_mut()
+                            .insert(CONNECTION, HeaderValue::from_static("close"));
+                        set_server(router, &mut resp);
+
+                        let mut context: Context<Http<_>> = Context::from(stream);
+                        context.send(resp).await?;
+                        return Ok(None);
+                    }
+                }
+            }
+
+            if stream.read_buf(bytes).await? == 0 {
+                return Ok(None);
             }
         };
 
+        info.next_request();
+        let client = router.client_info(&req, info.peer_addr());
+        req.extensions_mut().insert(info.clone());
+        req.extensions_mut().insert(client.clone());
+
         let (r, params) = router.route(&req);
 
-        let mut close = false;
+        let mut close = !keep_alive(&req);
 
         match &*r {
             Endpoint::Http(r) => {
-                let mut context: Context<Http<_>> = Context::from(stream);
-                let resp = r.handle(&req, &params).await?;
-                if let Some(v) = resp.headers().get("Connection") {
-                    if v == "close" {
-                        close = true;
-                    }
+                let mut resp = r.handle(&req, &params).await?;
+
+                log::info!(
+                    target: "supercruise::access",
+                    "{} {} {} {}",
+                    client.ip(),
+                    req.method(),
+                    req.uri(),
+                    resp.status().as_u16()
+                );
+
+                // event streams last until either side closes the connection
+                let events = sse::take(&mut resp);
+                close |= events.is_some() || has_token(resp.headers(), CONNECTION, "close");
+                *resp.version_mut() = req.version();
+                set_server(router, &mut resp);
+
+                if close {
+                    resp.headers_mut()
+                        .insert(CONNECTION, HeaderValue::from_static("close"));
+                } else if req.version() == Version::HTTP_10 {
+                    resp.headers_mut()
+                        .insert(CONNECTION, HeaderValue::from_static("keep-alive"));
                 }
 
+                if let Some(events) = events {
+                    sse::stream(stream, resp, events).await?;
+                    return Ok(None);
+                }
+
+                let mut context: Context<Http<_>> = Context::from(stream);
                 context.send(resp).await?;
             }
-            Endpoint::Ws(r) => {
-                WsUpgrader::upgrade(stream, &req).await?;
+            Endpoint::Ws(r, config) => {
+                let config = router.route_ws_config(config.as_ref());
 
-                let mut context = Context::<Ws>::from(stream);
-                let (mut tx, mut rx) = context.split();
-                r.handle(&mut tx, &mut rx, &params).await?;
+                let accepted = match WsUpgrader::validate(&req, &config) {
+                    Ok(()) => match r.upgrade(&req, &params).await {
+                        Some(resp) => Err(resp),
+                        None => WsUpgrader::accept(&req, &config).map_err(|e| e.response()),
+                    },
+                    Err(e) => Err(e.response()),
+                };
 
-                let close = WsFrame::builder().close();
-                tx.write(close).await?;
+                let (mut resp, handshake) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(mut resp) => {
+                        log::info!(
+                            target: "supercruise::access",
+                            "{} {} {} {}",
+                            client.ip(),
+                            req.method(),
+                            req.uri(),
+                            resp.status().as_u16()
+                        );
 
-                break;
+                        *resp.version_mut() = req.version();
+                        resp.headers_mut()
+                            .insert(CONNECTION, HeaderValue::from_static("close"));
+                        set_server(router, &mut resp);
+
+                        let mut context: Context<Http<_>> = Context::from(stream);
+                        context.send(resp).await?;
+                        return Ok(None);
+                    }
+                };
+
+                set_server(router, &mut resp);
+                WsUpgrader::upgrade(stream, resp).await?;
+
+                log::info!(
+                    target: "supercruise::access",
+                    "{} {} {} 101",
+                    client.ip(),
+                    req.method(),
+                    req.uri()
+                );
+
+                return Ok(Some((req, handshake)));
             }
         }
 
         if close {
-            break;
+            return Ok(None);
         }
     }
+}
+
+/// Runs the websocket route of an upgraded request on the connection.
+async fn websocket(
+    router: &'static Router,
+    stream: TcpStream,
+    mut req: Request<Body>,
+    handshake: Handshake,
+    bytes: BytesMut,
+) -> Result<(), ErrorEnum> {
+    let extensions = std::mem::take(req.extensions_mut());
+    let (r, params) = router.route(&req);
+
+    let (r, config) = match &*r {
+        Endpoint::Ws(r, config) => (r, router.route_ws_config(config.as_ref())),
+        Endpoint::Http(_) => unreachable!("upgraded request routed to http"),
+    };
+
+    let (mut tx, mut rx) = Context::<Ws>::split(stream);
+    ws::configure(&mut tx, &mut rx, &config, &handshake);
+    rx.extend_buf(&bytes);
+    *rx.extensions_mut() = extensions;
+
+    let res = r.handle(&mut tx, &mut rx, &params).await;
+
+    if let Err(e) = &res {
+        log::debug!("websocket handler error {}", e);
+    }
+
+    ws::shutdown(&mut tx, &mut rx, &config, res.is_err()).await?;
 
     Ok(())
 }
+
+/// Decides how to answer a request head carrying `Expect`. Returns whether
+/// `100 Continue` should be sent, or the response rejecting the request.
+async fn expect_continue(router: &Router, head: &Request<()>) -> Result<bool, Response<Body>> {
+    let expect = match head.headers().get(EXPECT) {
+        Some(expect) if head.version() == Version::HTTP_11 => expect,
+        _ => return Ok(false),
+    };
+
+    if !expect.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+        let resp = Response::builder()
+            .status(StatusCode::EXPECTATION_FAILED)
+            .body(().into())
+            .unwrap();
+
+        return Err(resp);
+    }
+
+    let (r, params) = router.route(head);
+
+    match &*r {
+        Endpoint::Http(r) => match r.expect_continue(head, &params).await {
+            Some(resp) => Err(resp),
+            None => Ok(true),
+        },
+        Endpoint::Ws(..) => Ok(true),
+    }
+}
+
+fn set_server<T>(router: &Router, resp: &mut Response<T>) {
+    if let Some(server) = router.server_header() {
+        resp.headers_mut()
+            .entry(SERVER)
+            .or_insert_with(|| server.clone());
+    }
+}
+
+/// HTTP/1.1 connections persist unless either side asks to close, HTTP/1.0
+/// connections only when the client explicitly asks for keep-alive.
+fn keep_alive(req: &Request<Body>) -> bool {
+    let headers = req.headers();
+
+    match req.version() {
+        Version::HTTP_11 => !has_token(headers, CONNECTION, "close"),
+        Version::HTTP_10 => has_token(headers, CONNECTION, "keep-alive"),
+        _ => false,
+    }
+}
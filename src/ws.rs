@@ -1,26 +1,371 @@
-use crate::codec::{http::Http, Encoder};
-use crate::context::Body;
-use base64::encode;
+use crate::codec::{
+    deflate::{Deflate, DeflateParams},
+    http::{has_token, Http},
+    websocket::{CloseCode, Opcode, Ws, WsFrame, MAX_FRAME, MAX_MESSAGE},
+    Encoder,
+};
+use crate::context::{Body, Overflow, Receiver, Sender};
+use base64::{decode, encode};
 use bytes::BytesMut;
-use http::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
-use http::{Request, Response, StatusCode};
+use http::header::{
+    HeaderMap, HeaderValue, CONNECTION, HOST, ORIGIN, SEC_WEBSOCKET_ACCEPT,
+    SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION,
+    UPGRADE,
+};
+use http::{Method, Request, Response, StatusCode, Version};
 use sha::sha1::Sha1;
 use sha::utils::{Digest, DigestExt};
 use std::fmt;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 
 pub struct WsUpgrader;
 
+/// Settings for websocket routes, see `Router::ws_config` and
+/// `Router::ws_with`.
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    close_timeout: Duration,
+    ping_interval: Option<Duration>,
+    pong_timeout: Duration,
+    deflate: Option<Deflate>,
+    protocols: Vec<String>,
+    origins: Vec<Origin>,
+    max_frame_size: usize,
+    max_message_size: usize,
+    send_queue: Option<(usize, Overflow)>,
+}
+
+#[derive(Debug, Clone)]
+enum Origin {
+    Exact(String),
+    Subdomain { scheme: String, domain: String },
+    Predicate(fn(&str) -> bool),
+}
+
+impl Origin {
+    fn parse(origin: &str) -> Self {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+
+        match origin.split_once("://*.") {
+            Some((scheme, domain)) => Self::Subdomain {
+                scheme: scheme.to_owned(),
+                domain: domain.to_owned(),
+            },
+            None => Self::Exact(origin),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Exact(exact) => origin.eq_ignore_ascii_case(exact),
+            Self::Subdomain { scheme, domain } => {
+                let origin = origin.to_ascii_lowercase();
+
+                let sub = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                    .and_then(|host| host.strip_suffix(domain.as_str()));
+
+                // at least one label followed by the dot before `domain`
+                matches!(sub, Some(sub) if sub.len() > 1 && sub.ends_with('.'))
+            }
+            Self::Predicate(f) => f(origin),
+        }
+    }
+}
+
+impl WsConfig {
+    pub fn new() -> Self {
+        Self {
+            close_timeout: Duration::from_secs(5),
+            ping_interval: None,
+            pong_timeout: Duration::from_secs(10),
+            deflate: None,
+            protocols: Vec::new(),
+            origins: Vec::new(),
+            max_frame_size: MAX_FRAME,
+            max_message_size: MAX_MESSAGE,
+            send_queue: None,
+        }
+    }
+
+    /// Sends a ping after `interval` without any traffic from the peer, to
+    /// keep idle connections open through proxies and load balancers.
+    /// Disabled by default.
+    ///
+    /// Pings are only sent, and their pongs only read, while the handler is
+    /// polling its `Receiver`. A handler that stops reading neither keeps
+    /// the connection alive nor notices a dead peer.
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    /// How long the peer has to answer a keepalive ping before the
+    /// connection is considered dead and closed. Defaults to 10 seconds.
+    pub fn pong_timeout(mut self, timeout: Duration) -> Self {
+        self.pong_timeout = timeout;
+        self
+    }
+
+    /// How long to wait for the peer to answer our close frame before the
+    /// connection is dropped. Defaults to 5 seconds.
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = timeout;
+        self
+    }
+
+    /// The largest frame payload accepted from the peer. Larger frames are
+    /// rejected from their header, before any of the payload is buffered,
+    /// and the connection is closed with 1009. Defaults to 16 MiB.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// The largest message accepted from the peer, summed over its fragments
+    /// and after decompression, see `max_frame_size`. Defaults to 64 MiB.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Queues outgoing messages, up to `capacity` of them, instead of
+    /// writing them straight to the socket, so a slow client only holds up
+    /// the senders when `overflow` is `Overflow::Block`. Queue depth and
+    /// counters are available from `Sender::queue_stats`. Disabled by
+    /// default.
+    pub fn send_queue(mut self, capacity: usize, overflow: Overflow) -> Self {
+        assert!(capacity > 0, "send queue capacity must be positive");
+        self.send_queue = Some((capacity, overflow));
+        self
+    }
+
+    /// Enables the permessage-deflate extension for clients that offer it.
+    pub fn deflate(mut self, deflate: Deflate) -> Self {
+        self.deflate = Some(deflate);
+        self
+    }
+
+    /// The subprotocols this route speaks, in order of preference. Once set,
+    /// clients must offer at least one of them in `Sec-WebSocket-Protocol`
+    /// or the upgrade is rejected. The chosen one is available from
+    /// `Receiver::protocol`.
+    pub fn protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Allows browsers on `origin` to connect, e.g. `https://example.com`,
+    /// or `https://*.example.com` for any of its subdomains. Once an origin
+    /// is allowed, upgrades from pages on other origins are rejected with
+    /// 403. Clients that send no `Origin` header, i.e. non-browser clients,
+    /// are not affected.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(Origin::parse(origin));
+        self
+    }
+
+    /// Allows the origins for which `f` returns true, see `allow_origin`.
+    pub fn allow_origin_fn(mut self, f: fn(&str) -> bool) -> Self {
+        self.origins.push(Origin::Predicate(f));
+        self
+    }
+
+    /// Whether a route configured with `self` falls back on the origins
+    /// allowed by the router wide `global` settings.
+    pub(crate) fn inherits_origins(&self, global: &WsConfig) -> bool {
+        self.origins.is_empty() && !global.origins.is_empty()
+    }
+
+    pub(crate) fn with_origins_of(mut self, global: &WsConfig) -> Self {
+        self.origins = global.origins.clone();
+        self
+    }
+
+    fn origin_allowed(&self, headers: &HeaderMap) -> bool {
+        let origin = match headers.get(ORIGIN) {
+            Some(origin) => origin,
+            None => return true,
+        };
+
+        if self.origins.is_empty() {
+            return true;
+        }
+
+        match origin.to_str() {
+            Ok(origin) => self.origins.iter().any(|o| o.matches(origin)),
+            Err(_) => false,
+        }
+    }
+
+    /// Picks our most preferred protocol among those the client offered.
+    fn select_protocol(&self, headers: &HeaderMap) -> Option<&str> {
+        let offered: Vec<&str> = headers
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .collect();
+
+        self.protocols
+            .iter()
+            .map(String::as_str)
+            .find(|p| offered.contains(p))
+    }
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What was agreed on with the client during the upgrade.
+#[derive(Debug, Default)]
+pub(crate) struct Handshake {
+    deflate: Option<DeflateParams>,
+    protocol: Option<String>,
+}
+
+/// Applies the route settings and negotiated extensions to a freshly
+/// upgraded connection.
+pub(crate) fn configure(
+    tx: &mut Sender<Ws>,
+    rx: &mut Receiver<Ws>,
+    config: &WsConfig,
+    handshake: &Handshake,
+) {
+    rx.set_limits(config.max_frame_size, config.max_message_size);
+
+    if let Some(interval) = config.ping_interval {
+        rx.set_keepalive(interval, config.pong_timeout);
+    }
+
+    if let Some(params) = &handshake.deflate {
+        tx.set_deflater(params.deflater());
+        rx.set_inflater(params.inflater());
+    }
+
+    if let Some(protocol) = &handshake.protocol {
+        rx.set_protocol(protocol.clone());
+    }
+
+    if let Some((capacity, overflow)) = config.send_queue {
+        tx.set_queue(capacity, overflow);
+    }
+}
+
+/// Runs the closing handshake once the route handler has returned.
+///
+/// A protocol error or dead peer fails the connection with its close code, a close from
+/// the peer is echoed back, otherwise a close frame is sent (1011 if the
+/// handler failed) and the peer's reply awaited up to the close timeout.
+/// Queued messages get the close timeout to be written.
+pub(crate) async fn shutdown(
+    tx: &mut Sender<Ws>,
+    rx: &mut Receiver<Ws>,
+    config: &WsConfig,
+    failed: bool,
+) -> std::io::Result<()> {
+    let res = close(tx, rx, config, failed).await;
+
+    let _ = tokio::time::timeout(config.close_timeout, tx.flush()).await;
+    tx.stop_queue();
+
+    res
+}
+
+async fn close(
+    tx: &mut Sender<Ws>,
+    rx: &mut Receiver<Ws>,
+    config: &WsConfig,
+    failed: bool,
+) -> std::io::Result<()> {
+    if let Some(err) = rx.error() {
+        let code = err.close_code();
+        return match tx.is_closed() {
+            true => Ok(()),
+            false => tx.close(code, "").await,
+        };
+    }
+
+    if let Some(frame) = rx.close_frame() {
+        if tx.is_closed() {
+            return Ok(());
+        }
+
+        return match frame.code() {
+            CloseCode::Status => tx.write(WsFrame::builder().close()).await,
+            code => tx.close(code, "").await,
+        };
+    }
+
+    if !tx.is_closed() {
+        let code = if failed {
+            CloseCode::Error
+        } else {
+            CloseCode::Normal
+        };
+
+        tx.close(code, "").await?;
+    }
+
+    let reply = async {
+        while let Ok(msg) = rx.recv().await {
+            if msg.opcode() == &Opcode::CLOSE {
+                break;
+            }
+        }
+    };
+
+    let _ = tokio::time::timeout(config.close_timeout, reply).await;
+
+    Ok(())
+}
+
+/// Why an upgrade request was rejected.
 #[derive(Debug)]
 pub enum WsUpgradeError {
-    UpgradeFailed,
+    /// The request is not a valid opening handshake.
+    BadRequest(&'static str),
+    /// The client speaks another `Sec-WebSocket-Version`.
+    UnsupportedVersion,
+    /// The request comes from a page on an origin that is not allowed.
+    Forbidden,
+}
+
+impl WsUpgradeError {
+    /// The response sent to the client, 400, 426 or 403 respectively.
+    pub fn response(&self) -> Response<Body> {
+        let builder = match self {
+            Self::BadRequest(_) => Response::builder().status(StatusCode::BAD_REQUEST),
+            Self::UnsupportedVersion => Response::builder()
+                .status(StatusCode::UPGRADE_REQUIRED)
+                .header(SEC_WEBSOCKET_VERSION, WsUpgrader::VERSION),
+            Self::Forbidden => Response::builder().status(StatusCode::FORBIDDEN),
+        };
+
+        builder
+            .header("Content-Type", "text/plain")
+            .body(self.to_string().into())
+            .unwrap()
+    }
 }
 
 impl std::fmt::Display for WsUpgradeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::UpgradeFailed => f.write_str("websocket upgrade failed"),
+            Self::BadRequest(reason) => f.write_str(reason),
+            Self::UnsupportedVersion => f.write_str("unsupported Sec-WebSocket-Version"),
+            Self::Forbidden => f.write_str("origin not allowed"),
         }
     }
 }
@@ -30,7 +375,6 @@ impl std::error::Error for WsUpgradeError {}
 #[derive(Debug)]
 pub enum ErrorEnum {
     IO(std::io::Error),
-    Ws(WsUpgradeError),
 }
 
 impl std::error::Error for ErrorEnum {}
@@ -39,7 +383,6 @@ impl std::fmt::Display for ErrorEnum {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::IO(err) => err.fmt(f),
-            Self::Ws(err) => err.fmt(f),
         }
     }
 }
@@ -52,57 +395,128 @@ impl From<std::io::Error> for ErrorEnum {
 
 impl WsUpgrader {
     const WS_KEY: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+    pub(crate) const VERSION: &'static str = "13";
 
-    pub async fn upgrade(
-        stream: &mut TcpStream,
-        req: &Request<Body>,
-    ) -> std::result::Result<(), ErrorEnum> {
+    /// Checks that `req` is a valid opening handshake as described in
+    /// RFC 6455 section 4.2.1 from an allowed origin, returning why it was
+    /// rejected if not.
+    pub(crate) fn validate(req: &Request<Body>, config: &WsConfig) -> Result<(), WsUpgradeError> {
         let headers = req.headers();
-        let mut buf = BytesMut::new();
 
-        if !headers.contains_key(CONNECTION) || !headers.contains_key(SEC_WEBSOCKET_KEY) {
-            let resp: Response<Body> = Response::builder()
-                .status(401)
-                .header("Content-Type", "text/plain")
-                .body("400 Bad Request".into())
-                .unwrap();
+        if req.method() != Method::GET {
+            return Err(WsUpgradeError::BadRequest("websocket upgrade requires GET"));
+        }
 
-            let mut http: Http<_> = Http::new();
-            http.encode(resp, &mut buf).unwrap();
-            stream.write(&buf).await?;
+        if req.version() != Version::HTTP_11 {
+            return Err(WsUpgradeError::BadRequest(
+                "websocket upgrade requires HTTP/1.1",
+            ));
+        }
 
-            return Err(ErrorEnum::Ws(WsUpgradeError::UpgradeFailed));
+        if !headers.contains_key(HOST) {
+            return Err(WsUpgradeError::BadRequest("missing Host header"));
+        }
+
+        if !has_token(headers, UPGRADE, "websocket") {
+            return Err(WsUpgradeError::BadRequest(
+                "Upgrade header must contain websocket",
+            ));
+        }
+
+        if !has_token(headers, CONNECTION, "upgrade") {
+            return Err(WsUpgradeError::BadRequest(
+                "Connection header must contain upgrade",
+            ));
+        }
+
+        let key = headers
+            .get(SEC_WEBSOCKET_KEY)
+            .and_then(|key| decode(key.as_bytes()).ok());
+
+        match key {
+            Some(key) if key.len() == 16 => {}
+            _ => return Err(WsUpgradeError::BadRequest("invalid Sec-WebSocket-Key")),
+        }
+
+        match headers.get(SEC_WEBSOCKET_VERSION) {
+            Some(version) if version == Self::VERSION => {}
+            _ => return Err(WsUpgradeError::UnsupportedVersion),
+        }
+
+        if !config.origin_allowed(headers) {
+            return Err(WsUpgradeError::Forbidden);
+        }
+
+        Ok(())
+    }
+
+    /// Negotiates the extensions and subprotocol of a validated request,
+    /// returning the `101 Switching Protocols` response to send.
+    pub(crate) fn accept(
+        req: &Request<Body>,
+        config: &WsConfig,
+    ) -> Result<(Response<()>, Handshake), WsUpgradeError> {
+        let headers = req.headers();
+        let mut handshake = Handshake::default();
+
+        if !config.protocols.is_empty() {
+            match config.select_protocol(headers) {
+                Some(protocol) => handshake.protocol = Some(protocol.to_owned()),
+                None => {
+                    return Err(WsUpgradeError::BadRequest(
+                        "no supported subprotocol offered",
+                    ))
+                }
+            }
         }
 
         let mut builder = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
             .header(UPGRADE, "websocket")
-            .header(CONNECTION, "Upgrade");
+            .header(CONNECTION, "Upgrade")
+            .header(
+                SEC_WEBSOCKET_ACCEPT,
+                Self::accept_key(&headers[SEC_WEBSOCKET_KEY]),
+            );
 
-        let key = format!(
-            "{}{}",
-            &headers[SEC_WEBSOCKET_KEY].to_str().unwrap(),
-            Self::WS_KEY
-        );
+        if let Some(protocol) = &handshake.protocol {
+            builder = builder.header(SEC_WEBSOCKET_PROTOCOL, protocol.as_str());
+        }
 
-        let len_a = &headers[SEC_WEBSOCKET_KEY].len();
-        let len_b = Self::WS_KEY.len();
+        if let Some(deflate) = &config.deflate {
+            let offers = headers
+                .get_all(SEC_WEBSOCKET_EXTENSIONS)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .find_map(|v| deflate.negotiate(v));
 
-        let mut bytes = Vec::with_capacity(len_a + len_b);
-        bytes.extend_from_slice(headers[SEC_WEBSOCKET_KEY].as_bytes());
-        bytes.extend_from_slice(Self::WS_KEY.as_bytes());
+            if let Some((params, response)) = offers {
+                builder = builder.header(SEC_WEBSOCKET_EXTENSIONS, response);
+                handshake.deflate = Some(params);
+            }
+        }
 
-        let hashed = Sha1::default().digest(key.as_bytes()).to_bytes();
-        let encoded = encode(hashed);
-        builder = builder.header(SEC_WEBSOCKET_ACCEPT, encoded);
+        Ok((builder.body(()).unwrap(), handshake))
+    }
 
-        let resp = builder
-            .status(StatusCode::SWITCHING_PROTOCOLS)
-            .body(())
-            .unwrap();
+    pub(crate) fn accept_key(key: &HeaderValue) -> String {
+        let mut bytes = Vec::with_capacity(key.len() + Self::WS_KEY.len());
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.extend_from_slice(Self::WS_KEY.as_bytes());
+
+        let hashed = Sha1::default().digest(&bytes).to_bytes();
+        encode(hashed)
+    }
 
+    /// Writes the `101 Switching Protocols` response.
+    pub(crate) async fn upgrade(
+        stream: &mut TcpStream,
+        resp: Response<()>,
+    ) -> std::result::Result<(), ErrorEnum> {
+        let mut buf = BytesMut::new();
         let mut http: Http<()> = Http::new();
         http.encode(resp, &mut buf).unwrap();
-        stream.write(&buf).await?;
+        stream.write_all(&buf).await?;
 
         Ok(())
     }
@@ -0,0 +1,327 @@
+use crate::codec::websocket::Ws;
+use crate::context::{Body, Receiver, Sender};
+use crate::loopback::connect;
+use crate::routing::Router;
+use crate::ws::{WsConfig, WsUpgrader};
+use http::header::{
+    HeaderValue, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION,
+};
+use http::{Method, Request, StatusCode, Version};
+
+fn upgrade_request() -> http::request::Builder {
+    Request::builder()
+        .uri("/chat")
+        .header("Host", "server.example.com")
+        .header("Upgrade", "websocket")
+        .header("Connection", "keep-alive, Upgrade")
+        .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+        .header("Sec-WebSocket-Version", "13")
+}
+
+#[test]
+fn ws_handshake_accepts_rfc_example() {
+    let req = upgrade_request().body(Body::empty()).unwrap();
+    assert!(WsUpgrader::validate(&req, &WsConfig::new()).is_ok());
+
+    let (resp, _) = WsUpgrader::accept(&req, &WsConfig::new()).unwrap();
+    assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(
+        resp.headers()[SEC_WEBSOCKET_ACCEPT],
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn ws_handshake_rejects_invalid_requests() {
+    let cases: [fn(&mut Request<Body>); 4] = [
+        |req| *req.method_mut() = Method::POST,
+        |req| *req.version_mut() = Version::HTTP_10,
+        |req| {
+            req.headers_mut().remove("Connection");
+        },
+        |req| {
+            let key = HeaderValue::from_static("c2hvcnQ=");
+            req.headers_mut().insert("Sec-WebSocket-Key", key);
+        },
+    ];
+
+    for case in cases {
+        let mut req = upgrade_request().body(Body::empty()).unwrap();
+        case(&mut req);
+
+        let resp = WsUpgrader::validate(&req, &WsConfig::new())
+            .unwrap_err()
+            .response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let mut req = upgrade_request().body(Body::empty()).unwrap();
+    let version = HeaderValue::from_static("8");
+    req.headers_mut().insert(SEC_WEBSOCKET_VERSION, version);
+
+    let resp = WsUpgrader::validate(&req, &WsConfig::new())
+        .unwrap_err()
+        .response();
+    assert_eq!(resp.status(), StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(resp.headers()[SEC_WEBSOCKET_VERSION], "13");
+}
+
+#[test]
+fn ws_handshake_selects_protocol() {
+    let config = WsConfig::new().protocols(["graphql-transport-ws", "bin.v1"]);
+
+    let req = upgrade_request()
+        .header("Sec-WebSocket-Protocol", "bin.v1, graphql-transport-ws")
+        .body(Body::empty())
+        .unwrap();
+
+    let (resp, _) = WsUpgrader::accept(&req, &config).unwrap();
+    assert_eq!(
+        resp.headers()[SEC_WEBSOCKET_PROTOCOL],
+        "graphql-transport-ws"
+    );
+
+    let req = upgrade_request()
+        .header("Sec-WebSocket-Protocol", "mqtt")
+        .body(Body::empty())
+        .unwrap();
+
+    let resp = WsUpgrader::accept(&req, &config).unwrap_err().response();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn ws_handshake_checks_origin() {
+    let config = WsConfig::new()
+        .allow_origin("https://example.com")
+        .allow_origin("https://*.example.org")
+        .allow_origin_fn(|origin| origin.starts_with("http://localhost:"));
+
+    let allowed = [
+        "https://example.com",
+        "HTTPS://EXAMPLE.COM",
+        "https://chat.example.org",
+        "https://a.b.example.org",
+        "http://localhost:3000",
+    ];
+
+    let denied = [
+        "https://evil.com",
+        "http://example.com",
+        "https://example.com.evil.com",
+        "https://example.org",
+        "https://evilexample.org",
+        "null",
+    ];
+
+    for (origin, ok) in allowed
+        .iter()
+        .map(|o| (o, true))
+        .chain(denied.iter().map(|o| (o, false)))
+    {
+        let req = upgrade_request()
+            .header("Origin", *origin)
+            .body(Body::empty())
+            .unwrap();
+
+        let res = WsUpgrader::validate(&req, &config);
+        assert_eq!(res.is_ok(), ok, "{}", origin);
+
+        if let Err(e) = res {
+            assert_eq!(e.response().status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    // non-browser clients send no origin at all
+    let req = upgrade_request().body(Body::empty()).unwrap();
+    assert!(WsUpgrader::validate(&req, &config).is_ok());
+}
+
+#[test]
+fn ws_route_config_keeps_router_origins() {
+    let router = Router::new().ws_config(WsConfig::new().allow_origin("https://example.com"));
+    let origin = |origin: &str| {
+        upgrade_request()
+            .header("Origin", origin)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // a route with settings of its own still only accepts the router's origins
+    let route = WsConfig::new().protocols(["chat"]);
+    let config = router.route_ws_config(Some(&route));
+    assert!(WsUpgrader::validate(&origin("https://example.com"), &config).is_ok());
+    assert!(WsUpgrader::validate(&origin("https://evil.com"), &config).is_err());
+
+    // unless it allows its own
+    let route = WsConfig::new().allow_origin("https://example.org");
+    let config = router.route_ws_config(Some(&route));
+    assert!(WsUpgrader::validate(&origin("https://example.org"), &config).is_ok());
+    assert!(WsUpgrader::validate(&origin("https://example.com"), &config).is_err());
+}
+
+#[test]
+fn ws_halves_are_owned() {
+    fn owned<T: Send + 'static>() {}
+
+    owned::<Sender<Ws>>();
+    owned::<Receiver<Ws>>();
+}
+
+#[tokio::test]
+async fn ws_stream_and_sink() {
+    use crate::codec::websocket::WsFrame;
+    use crate::prelude::{Message, Opcode};
+    use futures::{SinkExt, StreamExt};
+
+    let (mut client, mut tx, mut rx) = connect().await;
+    client
+        .send([
+            WsFrame::builder().text("a"),
+            WsFrame::builder().ping(),
+            WsFrame::builder().binary(vec![1, 2]),
+            WsFrame::builder().close(),
+        ])
+        .await;
+
+    // the ping is answered and the stream ends after the close message
+    let msgs: Vec<_> = (&mut rx).map(Result::unwrap).collect().await;
+    let opcodes: Vec<_> = msgs.iter().map(|m| *m.opcode()).collect();
+    assert_eq!(opcodes, [Opcode::TEXT, Opcode::BINARY, Opcode::CLOSE]);
+
+    // let the pong go out first
+    tokio::task::yield_now().await;
+
+    // the inherent methods take precedence over `SinkExt`
+    SinkExt::send(&mut tx, Message::text("b")).await.unwrap();
+    SinkExt::close(&mut tx).await.unwrap();
+    assert!(tx.is_closed());
+    drop((tx, rx));
+
+    let opcodes: Vec<_> = client
+        .read_to_end()
+        .await
+        .into_iter()
+        .map(|(opcode, _)| opcode)
+        .collect();
+    assert_eq!(opcodes, [Opcode::PONG, Opcode::TEXT, Opcode::CLOSE]);
+}
+
+/// Starts a fragmented message and only finishes it with the next message
+/// received, which the peer sends once its ping was answered.
+struct Fragmented;
+
+#[async_trait::async_trait]
+impl crate::routing::Route<Ws> for Fragmented {
+    async fn handle(
+        &self,
+        tx: &mut Sender<Ws>,
+        rx: &mut Receiver<Ws>,
+        _params: &trie_rs::params::Params,
+    ) -> std::io::Result<()> {
+        let mut fragments = tx.fragments(crate::prelude::Opcode::TEXT);
+        fragments.write("hel").await?;
+
+        let msg = rx.recv().await?;
+        fragments.finish(msg.data().to_vec()).await
+    }
+}
+
+#[tokio::test]
+async fn ws_pongs_between_fragments() {
+    use crate::codec::websocket::WsFrame;
+    use crate::prelude::{Message, Opcode};
+    use crate::testing::TestServer;
+    use std::time::Duration;
+
+    let server = TestServer::start(Router::new().ws("/", Fragmented))
+        .await
+        .unwrap();
+    let (mut tx, mut rx) = server.client().ws("/").await.unwrap();
+
+    let frame = rx.next().await.unwrap();
+    assert_eq!(*frame.opcode(), Opcode::TEXT);
+    assert!(!frame.fin());
+
+    tx.write(WsFrame::builder().ping()).await.unwrap();
+    let pong = tokio::time::timeout(Duration::from_secs(1), rx.next())
+        .await
+        .expect("no pong while a message is fragmented")
+        .unwrap();
+    assert_eq!(*pong.opcode(), Opcode::PONG);
+
+    tx.send(Message::text("lo")).await.unwrap();
+    let frame = rx.next().await.unwrap();
+    assert_eq!(*frame.opcode(), Opcode::CONTINUATION);
+    assert!(frame.fin());
+    assert_eq!(frame.data(), b"lo");
+}
+
+#[tokio::test]
+async fn ws_ping_flood_keeps_one_pong() {
+    use crate::prelude::{Message, Opcode};
+    use std::time::Duration;
+
+    const PINGS: usize = 20_000;
+
+    let (mut client, tx, mut rx) = connect().await;
+
+    let server = tokio::spawn(async move {
+        for _ in 0..PINGS {
+            assert_eq!(*rx.next().await.unwrap().opcode(), Opcode::PING);
+        }
+        (tx, rx)
+    });
+
+    // full size pings, far more pong bytes than the socket buffers hold
+    let pings = (0..PINGS).map(|i| Message::ping(format!("{:0125}", i)).into());
+    client.send(pings).await;
+
+    // every ping is read while the client reads nothing
+    let _halves = tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("receiver stalled on the unread pongs")
+        .unwrap();
+
+    let last = format!("{:0125}", PINGS - 1);
+    let mut pongs = 0;
+
+    loop {
+        let frame = client.next().await.unwrap();
+        assert_eq!(*frame.opcode(), Opcode::PONG);
+        pongs += 1;
+
+        if frame.data() == last.as_bytes() {
+            break;
+        }
+    }
+
+    assert!(pongs < PINGS / 2, "{} pongs for {} pings", pongs, PINGS);
+}
+
+#[tokio::test]
+async fn ws_keepalive_closes_silent_peers() {
+    use crate::prelude::Opcode;
+    use crate::ws::{configure, shutdown, Handshake};
+    use std::time::Duration;
+
+    let config = WsConfig::new()
+        .ping_interval(Duration::from_millis(20))
+        .pong_timeout(Duration::from_millis(20));
+
+    // the client reads nothing, so the ping is never answered
+    let (mut client, mut tx, mut rx) = connect().await;
+    configure(&mut tx, &mut rx, &config, &Handshake::default());
+
+    let err = rx.recv().await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    shutdown(&mut tx, &mut rx, &config, true).await.unwrap();
+    drop((tx, rx));
+
+    let frames = client.read_to_end().await;
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].0, Opcode::PING);
+    assert_eq!(frames[1].0, Opcode::CLOSE);
+    assert_eq!(&frames[1].1[..2], &1001u16.to_be_bytes());
+}